@@ -0,0 +1,30 @@
+#![no_main]
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use libfuzzer_sys::fuzz_target;
+use vban::sequence::Sequencer;
+use vban::{ReceiverStats, decode_packet};
+
+fuzz_target!(|data: &[u8]| {
+    // the input may hold several datagrams, separated like a receiver would
+    // see them arriving one after the other
+    let mut sequencer = None;
+    for packet in data.split(|&x| x == b'\n') {
+        if let Ok((header, samples)) = decode_packet(packet) {
+            assert_eq!(
+                samples.len(),
+                header.samples_per_frame as usize * header.channels as usize
+            );
+            let sequencer = sequencer.get_or_insert_with(|| {
+                Sequencer::new(
+                    Duration::from_millis(20),
+                    header.channels as usize,
+                    Arc::new(ReceiverStats::default()),
+                )
+            });
+            let _ = sequencer.push(&header, samples);
+        }
+    }
+});
@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use vban::VbanHeader;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(header) = VbanHeader::from_bytes(data) {
+        // everything a header parses from survives a round trip
+        assert_eq!(
+            VbanHeader::from_bytes(&header.to_bytes()).ok(),
+            Some(header)
+        );
+    }
+});
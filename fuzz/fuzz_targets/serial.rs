@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use vban::SerialPacket;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = SerialPacket::from_bytes(data);
+});
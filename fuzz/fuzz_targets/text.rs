@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use vban::TextPacket;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = TextPacket::from_bytes(data);
+});
@@ -0,0 +1,237 @@
+use std::str::FromStr;
+
+use crate::error::{Result, VbanError, unsupported};
+use crate::header::VBAN_MAX_CHANNELS;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Route {
+    source: usize,
+    destination: usize,
+    gain: f32,
+}
+
+/// Routes the channels of one interleaved layout to another.
+///
+/// Written as groups like `0,1->2,3`, separated by `;`. Within a group the
+/// sources are routed pairwise to the destinations, a single source is
+/// duplicated to every destination and several sources routed to a single
+/// destination are averaged into it. Destination channels nothing is routed
+/// to stay silent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelMap {
+    routes: Vec<Route>,
+}
+
+impl ChannelMap {
+    /// The mapping used when none is given: identical layouts are passed
+    /// through, mono is duplicated to every channel, anything is averaged
+    /// down to mono, and otherwise channels are routed one to one with
+    /// extra channels dropped
+    pub fn default_for(input_channels: usize, output_channels: usize) -> Self {
+        let routes = if input_channels == 1 {
+            (0..output_channels)
+                .map(|destination| Route {
+                    source: 0,
+                    destination,
+                    gain: 1.0,
+                })
+                .collect()
+        } else if output_channels == 1 {
+            (0..input_channels)
+                .map(|source| Route {
+                    source,
+                    destination: 0,
+                    gain: 1.0 / input_channels as f32,
+                })
+                .collect()
+        } else {
+            (0..input_channels.min(output_channels))
+                .map(|x| Route {
+                    source: x,
+                    destination: x,
+                    gain: 1.0,
+                })
+                .collect()
+        };
+        Self { routes }
+    }
+
+    /// Checks that every route fits the given channel counts
+    pub fn validate(&self, input_channels: usize, output_channels: usize) -> Result<()> {
+        for route in &self.routes {
+            if route.source >= input_channels {
+                unsupported!(
+                    "Channel map reads channel {} but there are only {} input channels",
+                    route.source,
+                    input_channels
+                );
+            }
+            if route.destination >= output_channels {
+                unsupported!(
+                    "Channel map writes channel {} but there are only {} output channels",
+                    route.destination,
+                    output_channels
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Maps interleaved `input` frames of `input_channels` channels to
+    /// interleaved frames of `output_channels` channels, ignoring routes
+    /// that don't fit
+    pub fn apply(&self, input: &[f32], input_channels: usize, output_channels: usize) -> Vec<f32> {
+        let frames = input.len() / input_channels;
+        let mut output = vec![0.0; frames * output_channels];
+        for (in_frame, out_frame) in input
+            .chunks_exact(input_channels)
+            .zip(output.chunks_exact_mut(output_channels))
+        {
+            for route in &self.routes {
+                if let (Some(sample), Some(out)) = (
+                    in_frame.get(route.source),
+                    out_frame.get_mut(route.destination),
+                ) {
+                    *out += sample * route.gain;
+                }
+            }
+        }
+        output
+    }
+}
+
+fn parse_channels(list: &str) -> Result<Vec<usize>> {
+    list.split(',')
+        .map(|x| {
+            let channel: usize = x.trim().parse().map_err(|_| {
+                VbanError::UnsupportedConfig(format!("Invalid channel '{}'", x.trim()))
+            })?;
+            if channel >= VBAN_MAX_CHANNELS {
+                unsupported!("Channel {} is out of range", channel);
+            }
+            Ok(channel)
+        })
+        .collect()
+}
+
+impl FromStr for ChannelMap {
+    type Err = VbanError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut routes = Vec::new();
+        for group in s.split(';').filter(|x| !x.trim().is_empty()) {
+            let (sources, destinations) = group.split_once("->").ok_or_else(|| {
+                VbanError::UnsupportedConfig(format!(
+                    "Expected 'sources->destinations', got '{}'",
+                    group
+                ))
+            })?;
+            let sources = parse_channels(sources)?;
+            let destinations = parse_channels(destinations)?;
+            match (sources.len(), destinations.len()) {
+                (1, _) => routes.extend(destinations.iter().map(|&destination| Route {
+                    source: sources[0],
+                    destination,
+                    gain: 1.0,
+                })),
+                (n, 1) => routes.extend(sources.iter().map(|&source| Route {
+                    source,
+                    destination: destinations[0],
+                    gain: 1.0 / n as f32,
+                })),
+                (n, m) if n == m => routes.extend(sources.iter().zip(&destinations).map(
+                    |(&source, &destination)| Route {
+                        source,
+                        destination,
+                        gain: 1.0,
+                    },
+                )),
+                (n, m) => unsupported!(
+                    "Cannot route {} channels to {} channels in '{}'",
+                    n,
+                    m,
+                    group
+                ),
+            }
+        }
+        if routes.is_empty() {
+            unsupported!("Channel map is empty");
+        }
+        Ok(Self { routes })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(s: &str) -> ChannelMap {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_groups() {
+        assert_eq!(map("0,1->2,3"), map("0->2;1->3"));
+        assert_eq!(map(" 0 -> 1 , 2 ;"), map("0->1;0->2"));
+        assert_eq!(map("0->0").apply(&[0.5, 0.25], 2, 1), [0.5]);
+    }
+
+    #[test]
+    fn rejects_invalid_maps() {
+        for s in ["", ";", "0,1", "0->x", "0->256", "0,1,2->3,4", "0->1->2"] {
+            assert!(
+                matches!(
+                    s.parse::<ChannelMap>(),
+                    Err(VbanError::UnsupportedConfig(_))
+                ),
+                "accepted '{}'",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn validates_channel_counts() {
+        let map = map("0,1->2,3");
+        assert!(map.validate(2, 4).is_ok());
+        assert!(map.validate(1, 4).is_err());
+        assert!(map.validate(2, 3).is_err());
+    }
+
+    #[test]
+    fn routes_singles_averages_and_pairs() {
+        // a single source is copied, several into one are averaged
+        assert_eq!(map("1->0,2").apply(&[0.0, 0.5], 2, 3), [0.5, 0.0, 0.5]);
+        assert_eq!(map("0,1->1").apply(&[0.5, 0.25], 2, 2), [0.0, 0.375]);
+        assert_eq!(
+            map("0,1->1,0").apply(&[0.5, 0.25, -0.5, -0.25], 2, 2),
+            [0.25, 0.5, -0.25, -0.5]
+        );
+    }
+
+    #[test]
+    fn default_maps() {
+        let frame = [0.5, -0.25, 0.125];
+        // 1->N copies, N->1 averages, N->M routes pairwise
+        assert_eq!(
+            ChannelMap::default_for(1, 3).apply(&[0.5], 1, 3),
+            [0.5, 0.5, 0.5]
+        );
+        assert_eq!(ChannelMap::default_for(3, 1).apply(&frame, 3, 1), [0.125]);
+        assert_eq!(
+            ChannelMap::default_for(3, 2).apply(&frame, 3, 2),
+            [0.5, -0.25]
+        );
+        assert_eq!(
+            ChannelMap::default_for(3, 4).apply(&frame, 3, 4),
+            [0.5, -0.25, 0.125, 0.0]
+        );
+        assert_eq!(ChannelMap::default_for(3, 3).apply(&frame, 3, 3), frame);
+    }
+
+    #[test]
+    fn apply_ignores_routes_that_do_not_fit() {
+        assert_eq!(map("0->5;3->0").apply(&[0.5, 0.25], 2, 2), [0.0, 0.0]);
+        assert!(map("0->0").apply(&[], 2, 2).is_empty());
+    }
+}
@@ -0,0 +1,235 @@
+use crate::error::{Result, VbanError, unsupported};
+use crate::header::{
+    DataFormat, VBAN_HEADER_SIZE, VBAN_MAX_PAYLOAD_SIZE, VBAN_MAX_SAMPLES_PER_FRAME, VbanHeader,
+    parse_audio_packet,
+};
+
+/// The most samples per channel a packet of `format` and `channels` can carry
+/// without exceeding either the 256 samples or the 1436 payload bytes limit
+pub fn max_samples_per_packet(format: DataFormat, channels: usize) -> usize {
+    let fit = VBAN_MAX_PAYLOAD_SIZE * 8 / (format.bits_per_sample() * channels);
+    fit.min(VBAN_MAX_SAMPLES_PER_FRAME)
+}
+
+/// Turns interleaved samples into VBAN packets of a fixed size, keeping track
+/// of the frame counter and any incomplete packet between calls
+#[derive(Debug, Clone)]
+pub struct Encoder {
+    header: VbanHeader,
+    samples_per_packet: usize,
+    pending: Vec<f32>,
+}
+
+impl Encoder {
+    /// Creates an encoder sending packets based on `header`, whose sample
+    /// count and frame counter are filled in for every packet. Each packet
+    /// carries `samples_per_packet` samples per channel, or as many as fit
+    /// if not set.
+    pub fn new(header: VbanHeader, samples_per_packet: Option<usize>) -> Result<Self> {
+        let max = max_samples_per_packet(header.data_format, header.channels as usize);
+        if max == 0 {
+            unsupported!(
+                "{} channels of {:?} do not fit in a VBAN packet",
+                header.channels,
+                header.data_format
+            );
+        }
+        let samples_per_packet = samples_per_packet.unwrap_or(max);
+        if samples_per_packet == 0 || samples_per_packet > max {
+            unsupported!(
+                "Packets of {} channels of {:?} carry 1 to {} samples",
+                header.channels,
+                header.data_format,
+                max
+            );
+        }
+        Ok(Self {
+            header,
+            samples_per_packet,
+            pending: Vec::new(),
+        })
+    }
+
+    /// The header packets are based on, with the frame counter of the next
+    /// packet
+    pub fn header(&self) -> &VbanHeader {
+        &self.header
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.header.channels
+    }
+
+    pub fn samples_per_packet(&self) -> usize {
+        self.samples_per_packet
+    }
+
+    /// Encodes interleaved samples into as many full packets as possible,
+    /// keeping the remainder for the next call
+    pub fn encode(&mut self, samples: &[f32]) -> Vec<Vec<u8>> {
+        let packet_len = self.samples_per_packet * self.header.channels as usize;
+        self.pending.extend_from_slice(samples);
+        let full = self.pending.len() / packet_len * packet_len;
+        self.header.samples_per_frame = self.samples_per_packet as u16;
+        let packets = self.pending[..full]
+            .chunks_exact(packet_len)
+            .map(|frame| encode_packet(&mut self.header, frame))
+            .collect();
+        self.pending.drain(..full);
+        packets
+    }
+
+    /// Encodes the remainder kept back by `encode` into a shorter last
+    /// packet, if there is one, at the end of a stream
+    pub fn flush(&mut self) -> Option<Vec<u8>> {
+        let channels = self.header.channels as usize;
+        let frames = self.pending.len() / channels;
+        if frames == 0 {
+            return None;
+        }
+        self.header.samples_per_frame = frames as u16;
+        let packet = encode_packet(&mut self.header, &self.pending[..frames * channels]);
+        self.pending.clear();
+        Some(packet)
+    }
+}
+
+/// Encodes one packet of `samples` after `header`, then moves the header on
+/// to the next frame
+fn encode_packet(header: &mut VbanHeader, samples: &[f32]) -> Vec<u8> {
+    let format = header.data_format;
+    let mut packet = Vec::with_capacity(VBAN_HEADER_SIZE + format.payload_size(samples.len()));
+    packet.extend_from_slice(&header.to_bytes());
+    encode_samples(format, samples, &mut packet);
+    header.frame_counter = header.frame_counter.wrapping_add(1);
+    packet
+}
+
+/// Parses a packet into its header and interleaved samples
+pub fn decode_packet(packet: &[u8]) -> Result<(VbanHeader, Vec<f32>)> {
+    let (header, payload) = parse_audio_packet(packet)?;
+    let count = header.samples_per_frame as usize * header.channels as usize;
+    let samples = decode_samples(header.data_format, payload, count)?;
+    Ok((header, samples))
+}
+
+/// Scale between a full-scale float sample and a signed integer of `bits` bits
+fn int_scale(bits: usize) -> f64 {
+    (1u64 << (bits - 1)) as f64
+}
+
+pub(crate) fn float_to_int(sample: f32, bits: usize) -> i64 {
+    let scale = int_scale(bits);
+    (sample as f64 * scale).round().clamp(-scale, scale - 1.0) as i64
+}
+
+fn int_to_float(sample: i64, bits: usize) -> f32 {
+    (sample as f64 / int_scale(bits)) as f32
+}
+
+/// Appends `samples` to `out` in the little-endian wire representation of
+/// `format`
+pub fn encode_samples(format: DataFormat, samples: &[f32], out: &mut Vec<u8>) {
+    match format {
+        DataFormat::U8 => out.extend(samples.iter().map(|&s| (float_to_int(s, 8) + 128) as u8)),
+        DataFormat::I16 => out.extend(
+            samples
+                .iter()
+                .flat_map(|&s| (float_to_int(s, 16) as i16).to_le_bytes()),
+        ),
+        DataFormat::I24 => {
+            for &s in samples {
+                out.extend_from_slice(&(float_to_int(s, 24) as i32).to_le_bytes()[..3]);
+            }
+        }
+        DataFormat::I32 => out.extend(
+            samples
+                .iter()
+                .flat_map(|&s| (float_to_int(s, 32) as i32).to_le_bytes()),
+        ),
+        DataFormat::F32 => out.extend(samples.iter().flat_map(|s| s.to_le_bytes())),
+        DataFormat::F64 => out.extend(samples.iter().flat_map(|&s| (s as f64).to_le_bytes())),
+        DataFormat::Bits12 | DataFormat::Bits10 => {
+            let bits = format.bits_per_sample();
+            let mask = (1u32 << bits) - 1;
+            // samples are packed LSB first into a continuous bit stream
+            let mut acc = 0u32;
+            let mut acc_bits = 0;
+            for &s in samples {
+                acc |= (float_to_int(s, bits) as u32 & mask) << acc_bits;
+                acc_bits += bits;
+                while acc_bits >= 8 {
+                    out.push(acc as u8);
+                    acc >>= 8;
+                    acc_bits -= 8;
+                }
+            }
+            if acc_bits > 0 {
+                out.push(acc as u8);
+            }
+        }
+    }
+}
+
+/// Decodes `count` samples of `format` from `payload`, failing if it holds
+/// fewer than `format.payload_size(count)` bytes
+pub fn decode_samples(format: DataFormat, payload: &[u8], count: usize) -> Result<Vec<f32>> {
+    // every sample takes at least a byte, which also keeps the size from
+    // overflowing
+    let size = (count <= payload.len()).then(|| format.payload_size(count));
+    let payload = size.and_then(|x| payload.get(..x)).ok_or_else(|| {
+        VbanError::MalformedPacket(format!(
+            "payload of {} bytes too short for {} samples of {:?}",
+            payload.len(),
+            count,
+            format
+        ))
+    })?;
+    Ok(match format {
+        DataFormat::U8 => payload
+            .iter()
+            .map(|&b| int_to_float(b as i64 - 128, 8))
+            .collect(),
+        DataFormat::I16 => payload
+            .chunks_exact(2)
+            .map(|b| int_to_float(i16::from_le_bytes([b[0], b[1]]) as i64, 16))
+            .collect(),
+        DataFormat::I24 => payload
+            .chunks_exact(3)
+            // shift into the top of an i32 to sign-extend
+            .map(|b| int_to_float((i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as i64, 24))
+            .collect(),
+        DataFormat::I32 => payload
+            .chunks_exact(4)
+            .map(|b| int_to_float(i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as i64, 32))
+            .collect(),
+        DataFormat::F32 => payload
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect(),
+        DataFormat::F64 => payload
+            .chunks_exact(8)
+            .map(|b| f64::from_le_bytes(b.try_into().unwrap()) as f32)
+            .collect(),
+        DataFormat::Bits12 | DataFormat::Bits10 => {
+            let bits = format.bits_per_sample();
+            let mut samples = Vec::with_capacity(count);
+            let mut acc = 0u32;
+            let mut acc_bits = 0;
+            let mut bytes = payload.iter();
+            while samples.len() < count {
+                while acc_bits < bits {
+                    acc |= (*bytes.next().unwrap_or(&0) as u32) << acc_bits;
+                    acc_bits += 8;
+                }
+                let raw = acc & ((1 << bits) - 1);
+                // sign-extend from `bits` bits
+                let value = ((raw << (32 - bits)) as i32) >> (32 - bits);
+                samples.push(int_to_float(value as i64, bits));
+                acc >>= bits;
+                acc_bits -= bits;
+            }
+            samples
+        }
+    })
+}
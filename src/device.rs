@@ -0,0 +1,132 @@
+// Stream construction for cpal devices of any sample format, see
+// https://github.com/RustAudio/cpal/blob/master/examples/beep.rs
+use cpal::traits::{DeviceTrait, HostTrait};
+use cpal::{
+    Device, FromSample, Host, InputCallbackInfo, OutputCallbackInfo, SampleFormat, SizedSample,
+    Stream, StreamConfig,
+};
+
+use crate::error::{Result, VbanError, unsupported};
+
+/// Looks up an input device by name, `default` standing for the host's
+/// default input device
+pub fn find_input_device(host: &Host, name: &str) -> Result<Device> {
+    let device = if name == "default" {
+        host.default_input_device()
+    } else {
+        host.input_devices()?
+            .find(|x| x.name().is_ok_and(|y| y == name))
+    };
+    device.ok_or_else(|| VbanError::DeviceNotFound(name.to_string()))
+}
+
+/// Looks up an output device by name, `default` standing for the host's
+/// default output device
+pub fn find_output_device(host: &Host, name: &str) -> Result<Device> {
+    let device = if name == "default" {
+        host.default_output_device()
+    } else {
+        host.output_devices()?
+            .find(|x| x.name().is_ok_and(|y| y == name))
+    };
+    device.ok_or_else(|| VbanError::DeviceNotFound(name.to_string()))
+}
+
+/// Builds an input stream in the device's sample format, handing the captured
+/// samples to `on_data` as f32
+pub fn build_input_stream<F>(
+    device: &Device,
+    config: &StreamConfig,
+    sample_format: SampleFormat,
+    on_data: F,
+) -> Result<Stream>
+where
+    F: FnMut(&[f32]) + Send + 'static,
+{
+    Ok(match sample_format {
+        SampleFormat::I8 => input_stream::<i8, F>(device, config, on_data)?,
+        SampleFormat::I16 => input_stream::<i16, F>(device, config, on_data)?,
+        SampleFormat::I32 => input_stream::<i32, F>(device, config, on_data)?,
+        SampleFormat::I64 => input_stream::<i64, F>(device, config, on_data)?,
+        SampleFormat::U8 => input_stream::<u8, F>(device, config, on_data)?,
+        SampleFormat::U16 => input_stream::<u16, F>(device, config, on_data)?,
+        SampleFormat::U32 => input_stream::<u32, F>(device, config, on_data)?,
+        SampleFormat::U64 => input_stream::<u64, F>(device, config, on_data)?,
+        SampleFormat::F32 => input_stream::<f32, F>(device, config, on_data)?,
+        SampleFormat::F64 => input_stream::<f64, F>(device, config, on_data)?,
+        format => unsupported!("Unsupported sample format '{}'", format),
+    })
+}
+
+/// Builds an output stream in the device's sample format, letting `fill`
+/// write the samples to play as f32
+pub fn build_output_stream<F>(
+    device: &Device,
+    config: &StreamConfig,
+    sample_format: SampleFormat,
+    fill: F,
+) -> Result<Stream>
+where
+    F: FnMut(&mut [f32]) + Send + 'static,
+{
+    Ok(match sample_format {
+        SampleFormat::I8 => output_stream::<i8, F>(device, config, fill)?,
+        SampleFormat::I16 => output_stream::<i16, F>(device, config, fill)?,
+        SampleFormat::I32 => output_stream::<i32, F>(device, config, fill)?,
+        SampleFormat::I64 => output_stream::<i64, F>(device, config, fill)?,
+        SampleFormat::U8 => output_stream::<u8, F>(device, config, fill)?,
+        SampleFormat::U16 => output_stream::<u16, F>(device, config, fill)?,
+        SampleFormat::U32 => output_stream::<u32, F>(device, config, fill)?,
+        SampleFormat::U64 => output_stream::<u64, F>(device, config, fill)?,
+        SampleFormat::F32 => output_stream::<f32, F>(device, config, fill)?,
+        SampleFormat::F64 => output_stream::<f64, F>(device, config, fill)?,
+        format => unsupported!("Unsupported sample format '{}'", format),
+    })
+}
+
+fn input_stream<T, F>(
+    device: &Device,
+    config: &StreamConfig,
+    mut on_data: F,
+) -> Result<Stream, cpal::BuildStreamError>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+    F: FnMut(&[f32]) + Send + 'static,
+{
+    let mut buffer = Vec::new();
+    device.build_input_stream(
+        config,
+        move |data: &[T], _: &InputCallbackInfo| {
+            buffer.clear();
+            buffer.extend(data.iter().map(|&s| s.to_sample::<f32>()));
+            on_data(&buffer);
+        },
+        |err| eprintln!("An error occurred on stream: {}", err),
+        None,
+    )
+}
+
+fn output_stream<T, F>(
+    device: &Device,
+    config: &StreamConfig,
+    mut fill: F,
+) -> Result<Stream, cpal::BuildStreamError>
+where
+    T: SizedSample + FromSample<f32>,
+    F: FnMut(&mut [f32]) + Send + 'static,
+{
+    let mut buffer = Vec::new();
+    device.build_output_stream(
+        config,
+        move |data: &mut [T], _: &OutputCallbackInfo| {
+            buffer.resize(data.len(), 0.0);
+            fill(&mut buffer);
+            for (d, &s) in data.iter_mut().zip(&buffer) {
+                *d = T::from_sample(s);
+            }
+        },
+        |err| eprintln!("Stream error: {}", err),
+        None,
+    )
+}
@@ -0,0 +1,112 @@
+// Errors of the library API
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+
+/// What went wrong in a library call, telling apart the failures callers
+/// may want to handle differently
+#[derive(Debug)]
+pub enum VbanError {
+    /// No audio device has this name
+    DeviceNotFound(String),
+    /// A device, file or setting cannot be used as asked, such as a sample
+    /// rate VBAN has no index for or a channel map that does not fit
+    UnsupportedConfig(String),
+    /// A socket could not be created or bound to this address, or set up
+    /// once bound
+    SocketBind(SocketAddr, io::Error),
+    /// A datagram is not a valid packet of the expected kind
+    MalformedPacket(String),
+    /// An audio stream could not be built, started or kept running
+    Stream(String),
+    /// Reading or writing a socket, file or serial port failed
+    Io(io::Error),
+}
+
+pub type Result<T, E = VbanError> = std::result::Result<T, E>;
+
+impl fmt::Display for VbanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VbanError::DeviceNotFound(name) => write!(f, "No audio device named \"{}\"", name),
+            VbanError::UnsupportedConfig(message) => write!(f, "{}", message),
+            VbanError::SocketBind(address, err) => {
+                write!(f, "Failed to bind a socket to {}: {}", address, err)
+            }
+            VbanError::MalformedPacket(message) => write!(f, "Malformed packet: {}", message),
+            VbanError::Stream(message) => write!(f, "Audio stream error: {}", message),
+            VbanError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for VbanError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            VbanError::SocketBind(_, err) | VbanError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for VbanError {
+    fn from(err: io::Error) -> Self {
+        VbanError::Io(err)
+    }
+}
+
+impl From<cpal::BuildStreamError> for VbanError {
+    fn from(err: cpal::BuildStreamError) -> Self {
+        match err {
+            cpal::BuildStreamError::StreamConfigNotSupported => {
+                VbanError::UnsupportedConfig(err.to_string())
+            }
+            err => VbanError::Stream(err.to_string()),
+        }
+    }
+}
+
+impl From<cpal::DevicesError> for VbanError {
+    fn from(err: cpal::DevicesError) -> Self {
+        VbanError::Stream(err.to_string())
+    }
+}
+
+impl From<cpal::PlayStreamError> for VbanError {
+    fn from(err: cpal::PlayStreamError) -> Self {
+        VbanError::Stream(err.to_string())
+    }
+}
+
+impl From<cpal::DefaultStreamConfigError> for VbanError {
+    fn from(err: cpal::DefaultStreamConfigError) -> Self {
+        VbanError::UnsupportedConfig(err.to_string())
+    }
+}
+
+impl From<cpal::SupportedStreamConfigsError> for VbanError {
+    fn from(err: cpal::SupportedStreamConfigsError) -> Self {
+        VbanError::UnsupportedConfig(err.to_string())
+    }
+}
+
+/// An `Io` error saying what was being done when `err` happened
+pub fn io_error(err: io::Error, context: impl fmt::Display) -> VbanError {
+    VbanError::Io(io::Error::new(err.kind(), format!("{}: {}", context, err)))
+}
+
+/// Returns an `UnsupportedConfig` error formatted like `format!`
+macro_rules! unsupported {
+    ($($arg:tt)*) => {
+        return Err($crate::error::VbanError::UnsupportedConfig(format!($($arg)*)))
+    };
+}
+
+/// Returns a `MalformedPacket` error formatted like `format!`
+macro_rules! malformed {
+    ($($arg:tt)*) => {
+        return Err($crate::error::VbanError::MalformedPacket(format!($($arg)*)))
+    };
+}
+
+pub(crate) use {malformed, unsupported};
@@ -0,0 +1,395 @@
+// A small FLAC encoder for recordings, using fixed predictors and a single
+// Rice partition per subframe, see https://xiph.org/flac/format.html
+use std::io::{self, Seek, SeekFrom, Write};
+
+/// Samples per channel in every frame but the last
+pub(crate) const BLOCK_SIZE: usize = 4096;
+
+/// Most channels a FLAC stream can carry
+pub(crate) const MAX_CHANNELS: usize = 8;
+
+/// Largest Rice parameter written, 15 being the escape code
+const MAX_RICE_PARAMETER: u32 = 14;
+
+/// Offset of the sample rate, channels, bits per sample and total samples
+/// fields of STREAMINFO
+const STREAM_INFO_FIELDS_OFFSET: u64 = 18;
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        Self {
+            bytes: Vec::new(),
+            acc: 0,
+            bits: 0,
+        }
+    }
+
+    /// Writes the low `bits` bits of `value`, at most 32
+    fn write(&mut self, value: u64, bits: u32) {
+        if bits == 0 {
+            return;
+        }
+        self.acc = (self.acc << bits) | (value & ((1 << bits) - 1));
+        self.bits += bits;
+        while self.bits >= 8 {
+            self.bits -= 8;
+            self.bytes.push((self.acc >> self.bits) as u8);
+        }
+        self.acc &= (1 << self.bits) - 1;
+    }
+
+    fn write_unary(&mut self, zeros: u64) {
+        let mut zeros = zeros;
+        while zeros >= 32 {
+            self.write(0, 32);
+            zeros -= 32;
+        }
+        self.write(1, zeros as u32 + 1);
+    }
+
+    /// Writes `n` in the UTF-8 like coding of frame numbers
+    fn write_utf8(&mut self, n: u64) {
+        if n < 0x80 {
+            self.write(n, 8);
+            return;
+        }
+        let len = (2..=7u32).find(|&x| n < 1 << (5 * x + 1)).unwrap_or(7);
+        let prefix = (0xFF00u64 >> len) & 0xFF;
+        self.write(prefix | (n >> (6 * (len - 1))), 8);
+        for i in (0..len - 1).rev() {
+            self.write(0x80 | ((n >> (6 * i)) & 0x3F), 8);
+        }
+    }
+
+    fn align(&mut self) {
+        if self.bits > 0 {
+            self.write(0, 8 - self.bits);
+        }
+    }
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Residuals of the fixed predictor of `order`
+fn fixed_residuals(samples: &[i64], order: usize) -> Vec<i64> {
+    (order..samples.len())
+        .map(|i| {
+            let x = |j: usize| samples[i - j];
+            match order {
+                0 => x(0),
+                1 => x(0) - x(1),
+                2 => x(0) - 2 * x(1) + x(2),
+                3 => x(0) - 3 * x(1) + 3 * x(2) - x(3),
+                _ => x(0) - 4 * x(1) + 6 * x(2) - 4 * x(3) + x(4),
+            }
+        })
+        .collect()
+}
+
+fn fold(residual: i64) -> u64 {
+    ((residual << 1) ^ (residual >> 63)) as u64
+}
+
+/// The Rice parameter coding `residuals` in the fewest bits, and that size
+fn best_rice_parameter(residuals: &[i64]) -> (u32, u64) {
+    (0..=MAX_RICE_PARAMETER)
+        .map(|k| {
+            let bits: u64 = residuals
+                .iter()
+                .map(|&r| (fold(r) >> k) + 1 + k as u64)
+                .sum();
+            (k, bits)
+        })
+        .min_by_key(|&(_, bits)| bits)
+        .unwrap_or((0, 0))
+}
+
+fn write_subframe(out: &mut BitWriter, samples: &[i64], bits_per_sample: u32) {
+    if samples.iter().all(|&x| x == samples[0]) {
+        out.write(0, 8);
+        out.write(samples[0] as u64, bits_per_sample);
+        return;
+    }
+
+    let verbatim_bits = samples.len() as u64 * bits_per_sample as u64;
+    let best = (0..=4.min(samples.len() - 1))
+        .map(|order| {
+            let residuals = fixed_residuals(samples, order);
+            let (k, bits) = best_rice_parameter(&residuals);
+            (
+                order,
+                residuals,
+                k,
+                bits + (order as u64) * bits_per_sample as u64 + 10,
+            )
+        })
+        // residuals have to fit in 32 bits
+        .filter(|(_, residuals, _, _)| residuals.iter().all(|&r| i32::try_from(r).is_ok()))
+        .min_by_key(|(_, _, _, bits)| *bits);
+
+    match best {
+        Some((order, residuals, k, bits)) if bits < verbatim_bits => {
+            out.write((0x08 | order as u64) << 1, 8);
+            for &x in &samples[..order] {
+                out.write(x as u64, bits_per_sample);
+            }
+            // Rice coding with 4-bit parameters, in a single partition
+            out.write(0, 2);
+            out.write(0, 4);
+            out.write(k as u64, 4);
+            for r in residuals {
+                let folded = fold(r);
+                out.write_unary(folded >> k);
+                out.write(folded, k);
+            }
+        }
+        _ => {
+            out.write(0x02, 8);
+            for &x in samples {
+                out.write(x as u64, bits_per_sample);
+            }
+        }
+    }
+}
+
+/// Writes interleaved integer samples as a FLAC stream
+pub(crate) struct FlacWriter<W: Write + Seek> {
+    writer: W,
+    rate: u32,
+    channels: usize,
+    bits_per_sample: u32,
+    pending: Vec<i32>,
+    frame_number: u64,
+    total_samples: u64,
+    bytes_written: u64,
+}
+
+impl<W: Write + Seek> FlacWriter<W> {
+    pub(crate) fn new(
+        mut writer: W,
+        rate: u32,
+        channels: usize,
+        bits_per_sample: u32,
+    ) -> io::Result<Self> {
+        if channels == 0 || channels > MAX_CHANNELS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("FLAC carries 1 to {} channels", MAX_CHANNELS),
+            ));
+        }
+        let mut header = BitWriter::new();
+        header.bytes.extend_from_slice(b"fLaC");
+        // the only, and so last, metadata block is a 34 byte STREAMINFO
+        header.write(0x80, 8);
+        header.write(34, 24);
+        header.write(BLOCK_SIZE as u64, 16);
+        header.write(BLOCK_SIZE as u64, 16);
+        // frame sizes and MD5 signature unknown
+        header.write(0, 24);
+        header.write(0, 24);
+        let mut writer_fields = Self::stream_info_fields(rate, channels, bits_per_sample, 0);
+        header.bytes.append(&mut writer_fields);
+        header.bytes.extend_from_slice(&[0; 16]);
+        writer.write_all(&header.bytes)?;
+        Ok(Self {
+            writer,
+            rate,
+            channels,
+            bits_per_sample,
+            pending: Vec::new(),
+            frame_number: 0,
+            total_samples: 0,
+            bytes_written: header.bytes.len() as u64,
+        })
+    }
+
+    fn stream_info_fields(
+        rate: u32,
+        channels: usize,
+        bits_per_sample: u32,
+        total_samples: u64,
+    ) -> Vec<u8> {
+        let mut fields = BitWriter::new();
+        fields.write(rate as u64, 20);
+        fields.write(channels as u64 - 1, 3);
+        fields.write(bits_per_sample as u64 - 1, 5);
+        fields.write(total_samples >> 32, 4);
+        fields.write(total_samples & 0xFFFF_FFFF, 32);
+        fields.bytes
+    }
+
+    pub(crate) fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    pub(crate) fn write(&mut self, samples: &[i32]) -> io::Result<()> {
+        self.pending.extend_from_slice(samples);
+        let block = BLOCK_SIZE * self.channels;
+        while self.pending.len() >= block {
+            let frame: Vec<i32> = self.pending.drain(..block).collect();
+            self.write_frame(&frame)?;
+        }
+        Ok(())
+    }
+
+    fn write_frame(&mut self, samples: &[i32]) -> io::Result<()> {
+        let frames = samples.len() / self.channels;
+        let mut out = BitWriter::new();
+        // sync code and fixed block size, with the block size at the end of
+        // the header and the rate and sample size taken from STREAMINFO
+        out.write(0xFFF8, 16);
+        out.write(0b0111, 4);
+        out.write(0, 4);
+        out.write(self.channels as u64 - 1, 4);
+        out.write(0, 4);
+        out.write_utf8(self.frame_number);
+        out.write(frames as u64 - 1, 16);
+        let crc = crc8(&out.bytes);
+        out.write(crc as u64, 8);
+
+        for channel in 0..self.channels {
+            let channel_samples: Vec<i64> = samples
+                .iter()
+                .skip(channel)
+                .step_by(self.channels)
+                .map(|&x| x as i64)
+                .collect();
+            write_subframe(&mut out, &channel_samples, self.bits_per_sample);
+        }
+        out.align();
+        let crc = crc16(&out.bytes);
+        out.bytes.extend_from_slice(&crc.to_be_bytes());
+
+        self.writer.write_all(&out.bytes)?;
+        self.bytes_written += out.bytes.len() as u64;
+        self.frame_number += 1;
+        self.total_samples += frames as u64;
+        Ok(())
+    }
+
+    /// Writes the last, shorter frame and the total length
+    pub(crate) fn finish(&mut self) -> io::Result<()> {
+        if !self.pending.is_empty() {
+            let frame = std::mem::take(&mut self.pending);
+            self.write_frame(&frame)?;
+        }
+        self.writer
+            .seek(SeekFrom::Start(STREAM_INFO_FIELDS_OFFSET))?;
+        self.writer.write_all(&Self::stream_info_fields(
+            self.rate,
+            self.channels,
+            self.bits_per_sample,
+            self.total_samples,
+        ))?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf8(n: u64) -> Vec<u8> {
+        let mut out = BitWriter::new();
+        out.write_utf8(n);
+        out.bytes
+    }
+
+    #[test]
+    fn codes_frame_numbers_like_utf8() {
+        assert_eq!(utf8(0), [0x00]);
+        assert_eq!(utf8(0x7F), [0x7F]);
+        assert_eq!(utf8(0x80), [0xC2, 0x80]);
+        assert_eq!(utf8(0x7FF), [0xDF, 0xBF]);
+        assert_eq!(utf8(0x800), [0xE0, 0xA0, 0x80]);
+        assert_eq!(utf8(0xFFFF), [0xEF, 0xBF, 0xBF]);
+        assert_eq!(utf8(0x10000), [0xF0, 0x90, 0x80, 0x80]);
+        assert_eq!(utf8(0x7FFF_FFFF), [0xFD, 0xBF, 0xBF, 0xBF, 0xBF, 0xBF]);
+        // FLAC extends the coding to 36 bits for sample numbers
+        assert_eq!(
+            utf8(0xF_FFFF_FFFF),
+            [0xFE, 0xBF, 0xBF, 0xBF, 0xBF, 0xBF, 0xBF]
+        );
+    }
+
+    #[test]
+    fn crcs_match_check_values() {
+        // the check values of CRC-8 and CRC-16/UMTS
+        assert_eq!(crc8(b"123456789"), 0xF4);
+        assert_eq!(crc16(b"123456789"), 0xFEE8);
+        assert_eq!(crc8(&[]), 0);
+        assert_eq!(crc16(&[]), 0);
+    }
+
+    /// The subframe header byte `samples` are written with
+    fn subframe_type(samples: &[i64]) -> u8 {
+        let mut out = BitWriter::new();
+        write_subframe(&mut out, samples, 16);
+        out.bytes[0]
+    }
+
+    #[test]
+    fn picks_the_smallest_subframe() {
+        assert_eq!(subframe_type(&[1234; 64]), 0x00);
+        // a ramp is predicted exactly from order 2 on, order 2 costing the
+        // fewest warm-up samples
+        let ramp: Vec<i64> = (0..64).map(|x| x * 100 - 3200).collect();
+        assert_eq!(subframe_type(&ramp), (0x08 | 2) << 1);
+        let mut seed = 0x2545_F491u32;
+        let noise: Vec<i64> = (0..64)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                seed as i16 as i64
+            })
+            .collect();
+        assert_eq!(subframe_type(&noise), 0x02);
+    }
+
+    #[test]
+    fn rice_codes_small_residuals_with_small_parameters() {
+        assert_eq!(best_rice_parameter(&[0; 16]), (0, 16));
+        let (k, _) = best_rice_parameter(&[1000, -1000, 900, -900]);
+        assert!((8..=10).contains(&k));
+        assert_eq!(fold(0), 0);
+        assert_eq!(fold(-1), 1);
+        assert_eq!(fold(1), 2);
+        assert_eq!(fold(-2), 3);
+    }
+}
@@ -0,0 +1,317 @@
+// VBAN packet header, see
+// https://vb-audio.com/Voicemeeter/VBANProtocol_Specifications.pdf
+use cpal::SampleRate;
+
+use crate::error::{Result, malformed};
+
+/// Size of the VBAN header in bytes
+pub const VBAN_HEADER_SIZE: usize = 28;
+
+/// The four bytes every VBAN packet starts with
+pub const VBAN_MAGIC: [u8; 4] = *b"VBAN";
+
+/// Size of the stream name field in bytes
+pub const VBAN_STREAM_NAME_SIZE: usize = 16;
+
+/// Maximum size of a VBAN packet, keeping it within a 1500 byte MTU
+pub const VBAN_MAX_PACKET_SIZE: usize = 1464;
+
+/// Maximum size of the payload following the header
+pub const VBAN_MAX_PAYLOAD_SIZE: usize = VBAN_MAX_PACKET_SIZE - VBAN_HEADER_SIZE;
+
+/// Maximum number of samples (per channel) a single packet can carry
+pub const VBAN_MAX_SAMPLES_PER_FRAME: usize = 256;
+
+/// Maximum number of channels a single packet can carry
+pub const VBAN_MAX_CHANNELS: usize = 256;
+
+/// Sample rates indexed by the 5-bit SR field of the header
+pub const VBAN_SAMPLE_RATES: [u32; 21] = [
+    6000, 12000, 24000, 48000, 96000, 192000, 384000, 8000, 16000, 32000, 64000, 128000, 256000,
+    512000, 11025, 22050, 44100, 88200, 176400, 352800, 705600,
+];
+
+/// Bit rates indexed by the 5-bit bps field of serial and text headers, 0
+/// meaning unspecified
+pub const VBAN_BIT_RATES: [u32; 25] = [
+    0, 110, 150, 300, 600, 1200, 2400, 4800, 9600, 14400, 19200, 31250, 38400, 57600, 115200,
+    128000, 230400, 250000, 256000, 460800, 921600, 1000000, 1500000, 2000000, 3000000,
+];
+
+const SUB_PROTOCOL_MASK: u8 = 0xE0;
+const SAMPLE_RATE_MASK: u8 = 0x1F;
+const DATA_FORMAT_MASK: u8 = 0x07;
+const CODEC_MASK: u8 = 0xF0;
+
+/// Looks up the SR index of a sample rate
+pub fn sample_rate_index(rate: SampleRate) -> Option<u8> {
+    VBAN_SAMPLE_RATES
+        .iter()
+        .position(|&x| x == rate.0)
+        .map(|x| x as u8)
+}
+
+/// The sample rate an SR index stands for
+pub fn sample_rate_from_index(index: u8) -> Option<SampleRate> {
+    VBAN_SAMPLE_RATES
+        .get(index as usize)
+        .copied()
+        .map(SampleRate)
+}
+
+/// Looks up the bps index of a serial or text bit rate
+pub fn bit_rate_index(bit_rate: u32) -> Option<u8> {
+    VBAN_BIT_RATES
+        .iter()
+        .position(|&x| x == bit_rate)
+        .map(|x| x as u8)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubProtocol {
+    Audio,
+    Serial,
+    Text,
+    Service,
+    Undefined1,
+    Undefined2,
+    Undefined3,
+    User,
+}
+
+impl SubProtocol {
+    fn from_bits(bits: u8) -> Self {
+        match bits & SUB_PROTOCOL_MASK {
+            0x00 => SubProtocol::Audio,
+            0x20 => SubProtocol::Serial,
+            0x40 => SubProtocol::Text,
+            0x60 => SubProtocol::Service,
+            0x80 => SubProtocol::Undefined1,
+            0xA0 => SubProtocol::Undefined2,
+            0xC0 => SubProtocol::Undefined3,
+            _ => SubProtocol::User,
+        }
+    }
+
+    fn bits(self) -> u8 {
+        match self {
+            SubProtocol::Audio => 0x00,
+            SubProtocol::Serial => 0x20,
+            SubProtocol::Text => 0x40,
+            SubProtocol::Service => 0x60,
+            SubProtocol::Undefined1 => 0x80,
+            SubProtocol::Undefined2 => 0xA0,
+            SubProtocol::Undefined3 => 0xC0,
+            SubProtocol::User => 0xE0,
+        }
+    }
+}
+
+/// The sample representation of the audio payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum DataFormat {
+    U8,
+    I16,
+    I24,
+    I32,
+    F32,
+    F64,
+    Bits12,
+    Bits10,
+}
+
+impl DataFormat {
+    pub fn bits_per_sample(self) -> usize {
+        match self {
+            DataFormat::U8 => 8,
+            DataFormat::I16 => 16,
+            DataFormat::I24 => 24,
+            DataFormat::I32 | DataFormat::F32 => 32,
+            DataFormat::F64 => 64,
+            DataFormat::Bits12 => 12,
+            DataFormat::Bits10 => 10,
+        }
+    }
+
+    /// Bytes needed to carry `samples` samples, the 12 and 10 bit formats
+    /// being packed without padding between samples
+    pub fn payload_size(self, samples: usize) -> usize {
+        (samples * self.bits_per_sample()).div_ceil(8)
+    }
+
+    fn from_bits(bits: u8) -> Self {
+        match bits & DATA_FORMAT_MASK {
+            0 => DataFormat::U8,
+            1 => DataFormat::I16,
+            2 => DataFormat::I24,
+            3 => DataFormat::I32,
+            4 => DataFormat::F32,
+            5 => DataFormat::F64,
+            6 => DataFormat::Bits12,
+            _ => DataFormat::Bits10,
+        }
+    }
+
+    fn bits(self) -> u8 {
+        match self {
+            DataFormat::U8 => 0,
+            DataFormat::I16 => 1,
+            DataFormat::I24 => 2,
+            DataFormat::I32 => 3,
+            DataFormat::F32 => 4,
+            DataFormat::F64 => 5,
+            DataFormat::Bits12 => 6,
+            DataFormat::Bits10 => 7,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    Pcm,
+    /// VB-Audio AOIP codec
+    Vbca,
+    /// VB-Audio VOIP codec
+    Vbcv,
+    Undefined(u8),
+    User,
+}
+
+impl Codec {
+    fn from_bits(bits: u8) -> Self {
+        match bits & CODEC_MASK {
+            0x00 => Codec::Pcm,
+            0x10 => Codec::Vbca,
+            0x20 => Codec::Vbcv,
+            0xF0 => Codec::User,
+            x => Codec::Undefined(x),
+        }
+    }
+
+    fn bits(self) -> u8 {
+        match self {
+            Codec::Pcm => 0x00,
+            Codec::Vbca => 0x10,
+            Codec::Vbcv => 0x20,
+            Codec::Undefined(x) => x & CODEC_MASK,
+            Codec::User => 0xF0,
+        }
+    }
+}
+
+/// The 28-byte header sent in front of every VBAN payload
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VbanHeader {
+    pub sub_protocol: SubProtocol,
+    /// Index into `VBAN_SAMPLE_RATES`
+    pub sample_rate_index: u8,
+    /// Samples per channel in the packet, 1..=256
+    pub samples_per_frame: u16,
+    /// Number of channels in the packet, 1..=256
+    pub channels: u16,
+    pub data_format: DataFormat,
+    pub codec: Codec,
+    pub stream_name: [u8; VBAN_STREAM_NAME_SIZE],
+    /// Incremented by the sender for every packet of the stream
+    pub frame_counter: u32,
+}
+
+impl Default for VbanHeader {
+    fn default() -> Self {
+        Self {
+            sub_protocol: SubProtocol::Audio,
+            sample_rate_index: 3,
+            samples_per_frame: 1,
+            channels: 1,
+            data_format: DataFormat::I16,
+            codec: Codec::Pcm,
+            stream_name: [0; VBAN_STREAM_NAME_SIZE],
+            frame_counter: 0,
+        }
+    }
+}
+
+impl VbanHeader {
+    /// The sample rate, if the SR index is a known one
+    pub fn sample_rate(&self) -> Option<SampleRate> {
+        sample_rate_from_index(self.sample_rate_index)
+    }
+
+    /// The stream name up to the first null byte
+    pub fn stream_name(&self) -> String {
+        let len = self
+            .stream_name
+            .iter()
+            .position(|&x| x == 0)
+            .unwrap_or(VBAN_STREAM_NAME_SIZE);
+        String::from_utf8_lossy(&self.stream_name[..len]).into_owned()
+    }
+
+    /// Sets the stream name, truncating it to 16 bytes
+    pub fn set_stream_name(&mut self, name: &str) {
+        self.stream_name = [0; VBAN_STREAM_NAME_SIZE];
+        let len = name.len().min(VBAN_STREAM_NAME_SIZE);
+        self.stream_name[..len].copy_from_slice(&name.as_bytes()[..len]);
+    }
+
+    pub fn to_bytes(&self) -> [u8; VBAN_HEADER_SIZE] {
+        let mut bytes = [0u8; VBAN_HEADER_SIZE];
+        bytes[0..4].copy_from_slice(&VBAN_MAGIC);
+        bytes[4] = self.sub_protocol.bits() | (self.sample_rate_index & SAMPLE_RATE_MASK);
+        bytes[5] = (self.samples_per_frame.clamp(1, 256) - 1) as u8;
+        bytes[6] = (self.channels.clamp(1, 256) - 1) as u8;
+        bytes[7] = self.codec.bits() | self.data_format.bits();
+        bytes[8..24].copy_from_slice(&self.stream_name);
+        bytes[24..28].copy_from_slice(&self.frame_counter.to_le_bytes());
+        bytes
+    }
+
+    /// Parses the header at the start of a packet
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < VBAN_HEADER_SIZE {
+            malformed!("too short for a VBAN header: {} bytes", bytes.len());
+        }
+        if bytes[0..4] != VBAN_MAGIC {
+            malformed!("does not start with the VBAN magic");
+        }
+        let mut stream_name = [0; VBAN_STREAM_NAME_SIZE];
+        stream_name.copy_from_slice(&bytes[8..24]);
+        Ok(Self {
+            sub_protocol: SubProtocol::from_bits(bytes[4]),
+            sample_rate_index: bytes[4] & SAMPLE_RATE_MASK,
+            samples_per_frame: bytes[5] as u16 + 1,
+            channels: bytes[6] as u16 + 1,
+            data_format: DataFormat::from_bits(bytes[7]),
+            codec: Codec::from_bits(bytes[7]),
+            stream_name,
+            frame_counter: u32::from_le_bytes([bytes[24], bytes[25], bytes[26], bytes[27]]),
+        })
+    }
+}
+
+/// Splits a packet into its header and payload, checking that the payload
+/// length matches what the header announces for PCM audio
+pub fn parse_audio_packet(packet: &[u8]) -> Result<(VbanHeader, &[u8])> {
+    let header = VbanHeader::from_bytes(packet)?;
+    if header.sub_protocol != SubProtocol::Audio {
+        malformed!("not an audio packet: {:?}", header.sub_protocol);
+    }
+    if header.codec != Codec::Pcm {
+        malformed!("unsupported codec: {:?}", header.codec);
+    }
+    if header.sample_rate().is_none() {
+        malformed!("unknown sample rate index {}", header.sample_rate_index);
+    }
+    let payload = &packet[VBAN_HEADER_SIZE..];
+    let expected = header
+        .data_format
+        .payload_size(header.samples_per_frame as usize * header.channels as usize);
+    if payload.len() != expected {
+        malformed!(
+            "payload length {} does not match the header ({} bytes expected)",
+            payload.len(),
+            expected
+        );
+    }
+    Ok((header, payload))
+}
@@ -0,0 +1,295 @@
+// Buffering between the network thread and the playback callback
+use std::sync::Arc;
+use std::time::Duration;
+
+use cpal::SampleRate;
+use ringbuf::traits::{Consumer, Observer, Producer, Split};
+use ringbuf::{HeapCons, HeapProd, HeapRb};
+
+use crate::error::{Result, unsupported};
+use crate::header::VBAN_MAX_SAMPLES_PER_FRAME;
+use crate::stats::ReceiverStats;
+
+/// The longest a packet can last at 48 kHz, which `max` must leave room for
+const PACKET_DURATION: Duration =
+    Duration::from_nanos(VBAN_MAX_SAMPLES_PER_FRAME as u64 * 1_000_000_000 / 48000);
+
+/// The most frames a playback callback is expected to ask for at once
+const MAX_CALLBACK_FRAMES: usize = 4096;
+
+/// Depths of the jitter buffer, as durations of audio
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JitterConfig {
+    /// Depth to fill up to before playback starts or resumes
+    pub target: Duration,
+    /// Depth below which playback pauses to refill up to `target`
+    pub min: Duration,
+    /// Depth above which the oldest audio is dropped down to `target`
+    pub max: Duration,
+}
+
+impl JitterConfig {
+    /// A config buffering `latency`, with room for four times that, or at
+    /// least a packet, before audio is dropped
+    pub fn new(latency: Duration) -> Self {
+        Self {
+            target: latency,
+            min: Duration::ZERO,
+            max: (latency * 4).max(PACKET_DURATION),
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.target.is_zero() {
+            unsupported!("Jitter buffer target must be above zero");
+        }
+        if self.max < PACKET_DURATION {
+            unsupported!(
+                "Jitter buffer max must hold a packet of {:?}, got {:?}",
+                PACKET_DURATION,
+                self.max
+            );
+        }
+        if self.min > self.target || self.target > self.max {
+            unsupported!(
+                "Jitter buffer depths must satisfy min <= target <= max, got {:?} <= {:?} <= {:?}",
+                self.min,
+                self.target,
+                self.max
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Creates the two halves of a jitter buffer for interleaved audio at `rate`
+/// with `channels` channels
+pub fn jitter_buffer(
+    config: &JitterConfig,
+    rate: SampleRate,
+    channels: usize,
+    stats: Arc<ReceiverStats>,
+) -> (JitterProducer, JitterConsumer) {
+    // depths are kept in whole frames so that channels never get shifted
+    let samples = |x: Duration| (x.as_secs_f64() * rate.0 as f64).round() as usize * channels;
+    let max = samples(config.max).max(channels);
+    // a full packet arriving at `max` is kept while the callback catches up
+    let capacity = max + (VBAN_MAX_SAMPLES_PER_FRAME + MAX_CALLBACK_FRAMES) * channels;
+    let (producer, consumer) = HeapRb::new(capacity).split();
+    (
+        JitterProducer {
+            producer,
+            stats: stats.clone(),
+        },
+        JitterConsumer {
+            consumer,
+            channels,
+            target: samples(config.target),
+            min: samples(config.min),
+            max,
+            buffering: true,
+            stats,
+        },
+    )
+}
+
+/// The network side of the jitter buffer
+pub struct JitterProducer {
+    producer: HeapProd<f32>,
+    stats: Arc<ReceiverStats>,
+}
+
+impl JitterProducer {
+    /// Queues interleaved samples, dropping them if the buffer is full
+    pub fn push(&mut self, samples: &[f32]) {
+        if self.producer.vacant_len() < samples.len() {
+            ReceiverStats::count(&self.stats.overruns);
+            return;
+        }
+        self.producer.push_slice(samples);
+    }
+}
+
+/// The playback side of the jitter buffer
+pub struct JitterConsumer {
+    consumer: HeapCons<f32>,
+    channels: usize,
+    target: usize,
+    min: usize,
+    max: usize,
+    buffering: bool,
+    stats: Arc<ReceiverStats>,
+}
+
+impl JitterConsumer {
+    /// Fills `data` with buffered samples, or silence while the buffer is
+    /// refilling. Depths are counted past what `data` takes, so that a
+    /// callback longer than `max` still plays.
+    pub fn fill(&mut self, data: &mut [f32]) {
+        let level = self.consumer.occupied_len();
+        // the most a callback can wait for, as packets only go in whole
+        let reachable = self.consumer.capacity().get() - VBAN_MAX_SAMPLES_PER_FRAME * self.channels;
+        let start = self.target.max(data.len()).min(reachable);
+        if level > self.max + data.len() {
+            let excess = (level - start) / self.channels * self.channels;
+            self.consumer.skip(excess);
+            ReceiverStats::count(&self.stats.overruns);
+        } else if !self.buffering && level < self.min {
+            self.buffering = true;
+            ReceiverStats::count(&self.stats.underruns);
+        }
+
+        if self.buffering {
+            if self.consumer.occupied_len() < start {
+                data.fill(0.0);
+                return;
+            }
+            self.buffering = false;
+        }
+
+        let read = self.consumer.pop_slice(data);
+        if read < data.len() {
+            data[read..].fill(0.0);
+            self.buffering = true;
+            ReceiverStats::count(&self.stats.underruns);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use super::*;
+
+    /// A stereo buffer at 1 kHz, where a millisecond is one frame: 20
+    /// samples target, 8 min and 80 max
+    fn buffer() -> (JitterProducer, JitterConsumer, Arc<ReceiverStats>) {
+        let config = JitterConfig {
+            target: Duration::from_millis(10),
+            min: Duration::from_millis(4),
+            max: Duration::from_millis(40),
+        };
+        let stats = Arc::new(ReceiverStats::default());
+        let (producer, consumer) = jitter_buffer(&config, SampleRate(1000), 2, stats.clone());
+        (producer, consumer, stats)
+    }
+
+    /// Samples numbered from `start`
+    fn ramp(start: usize, len: usize) -> Vec<f32> {
+        (start..start + len).map(|x| x as f32).collect()
+    }
+
+    fn fill(consumer: &mut JitterConsumer, len: usize) -> Vec<f32> {
+        let mut data = vec![-1.0; len];
+        consumer.fill(&mut data);
+        data
+    }
+
+    fn counts(stats: &ReceiverStats) -> (u64, u64) {
+        (
+            stats.underruns.load(Ordering::Relaxed),
+            stats.overruns.load(Ordering::Relaxed),
+        )
+    }
+
+    #[test]
+    fn fills_to_target_before_playing() {
+        let (mut producer, mut consumer, stats) = buffer();
+        producer.push(&ramp(1, 18));
+        assert_eq!(fill(&mut consumer, 4), [0.0; 4]);
+        producer.push(&ramp(19, 2));
+        assert_eq!(fill(&mut consumer, 4), ramp(1, 4));
+        assert_eq!(counts(&stats), (0, 0));
+    }
+
+    #[test]
+    fn refills_once_below_min() {
+        let (mut producer, mut consumer, stats) = buffer();
+        producer.push(&ramp(1, 20));
+        assert_eq!(fill(&mut consumer, 12), ramp(1, 12));
+        // 8 samples left is still at min
+        assert_eq!(fill(&mut consumer, 2), ramp(13, 2));
+        assert_eq!(fill(&mut consumer, 2), [0.0; 2]);
+        assert_eq!(counts(&stats), (1, 0));
+        // nothing plays until the target is reached again
+        producer.push(&ramp(21, 12));
+        assert_eq!(fill(&mut consumer, 2), [0.0; 2]);
+        producer.push(&ramp(33, 2));
+        assert_eq!(fill(&mut consumer, 2), ramp(15, 2));
+        assert_eq!(counts(&stats), (1, 0));
+    }
+
+    #[test]
+    fn pads_with_silence_when_running_dry() {
+        let (mut producer, mut consumer, stats) = buffer();
+        producer.push(&ramp(1, 20));
+        assert_eq!(fill(&mut consumer, 4), ramp(1, 4));
+        let data = fill(&mut consumer, 24);
+        assert_eq!(data[..16], ramp(5, 16));
+        assert_eq!(data[16..], [0.0; 8]);
+        assert_eq!(counts(&stats), (1, 0));
+    }
+
+    #[test]
+    fn drops_whole_frames_above_max() {
+        let (mut producer, mut consumer, stats) = buffer();
+        producer.push(&ramp(1, 100));
+        // down to the target, the oldest frames going first
+        assert_eq!(fill(&mut consumer, 4), ramp(81, 4));
+        assert_eq!(counts(&stats), (0, 1));
+    }
+
+    #[test]
+    fn drops_packets_that_do_not_fit() {
+        let (mut producer, mut consumer, stats) = buffer();
+        let capacity = consumer.consumer.capacity().get();
+        producer.push(&ramp(1, capacity));
+        producer.push(&ramp(capacity + 1, 20));
+        assert_eq!(counts(&stats), (0, 1));
+        assert_eq!(fill(&mut consumer, 20), ramp(capacity - 19, 20));
+        assert_eq!(counts(&stats), (0, 2));
+    }
+
+    #[test]
+    fn plays_callbacks_longer_than_max() {
+        let (mut producer, mut consumer, stats) = buffer();
+        // full packets and a callback of two of them, all above max
+        let packet = VBAN_MAX_SAMPLES_PER_FRAME * 2;
+        producer.push(&ramp(1, packet));
+        assert_eq!(fill(&mut consumer, packet * 2), vec![0.0; packet * 2]);
+        producer.push(&ramp(packet + 1, packet));
+        for i in 0..4 {
+            assert_eq!(
+                fill(&mut consumer, packet * 2),
+                ramp(i * packet * 2 + 1, packet * 2)
+            );
+            producer.push(&ramp((i * 2 + 2) * packet + 1, packet));
+            producer.push(&ramp((i * 2 + 3) * packet + 1, packet));
+        }
+        assert_eq!(counts(&stats), (0, 0));
+    }
+
+    #[test]
+    fn rejects_depths_that_cannot_play() {
+        let latency = Duration::from_millis(10);
+        assert!(JitterConfig::new(latency).validate().is_ok());
+        // the default max holds a packet even when four times the target would not
+        assert!(
+            JitterConfig::new(Duration::from_millis(1))
+                .validate()
+                .is_ok()
+        );
+        assert!(JitterConfig::new(Duration::ZERO).validate().is_err());
+        let small_max = JitterConfig {
+            max: Duration::from_millis(2),
+            ..JitterConfig::new(Duration::from_millis(1))
+        };
+        assert!(small_max.validate().is_err());
+        let reversed = JitterConfig {
+            min: latency * 2,
+            ..JitterConfig::new(latency)
+        };
+        assert!(reversed.validate().is_err());
+    }
+}
@@ -0,0 +1,45 @@
+//! Cross-platform VBAN audio streaming.
+//!
+//! The packet layer (`header`, `codec`) does no I/O, while `VbanSender` and
+//! `VbanReceiver` connect it to sockets and to audio sources and sinks, be
+//! they cpal devices, files, generators or memory.
+
+pub mod channels;
+pub mod codec;
+pub mod device;
+pub mod error;
+mod flac;
+pub mod header;
+pub mod jitter;
+pub mod mixer;
+pub mod net;
+pub mod receiver;
+pub mod record;
+pub mod resample;
+pub mod sender;
+pub mod sequence;
+pub mod serial;
+pub mod service;
+pub mod sink;
+pub mod source;
+pub mod stats;
+pub mod target;
+pub mod text;
+
+pub use channels::ChannelMap;
+pub use codec::{Encoder, decode_packet};
+pub use error::VbanError;
+pub use header::{Codec, DataFormat, SubProtocol, VbanHeader};
+pub use jitter::JitterConfig;
+pub use net::Interface;
+pub use receiver::{ReceiverConfig, VbanReceiver};
+pub use record::RecordConfig;
+pub use resample::ResamplerQuality;
+pub use sender::{SenderConfig, VbanSender};
+pub use serial::{SerialFormat, SerialListener, SerialPacket, SerialSender, SerialSenderConfig};
+pub use service::{Identity, PingPacket, discover};
+pub use sink::{AudioSink, DeviceSink, MemorySink, NullSink};
+pub use source::{AudioSource, DeviceSource, FileSource, GeneratorSource, MemorySource, Waveform};
+pub use stats::ReceiverStats;
+pub use target::Target;
+pub use text::{TextFormat, TextListener, TextPacket, TextSender, TextSenderConfig};
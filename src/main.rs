@@ -1,11 +1,24 @@
-use std::net::{SocketAddr, UdpSocket};
-use std::sync::mpsc;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, Read, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::process;
 use std::thread::{self};
 use std::time::Duration;
 
 use clap::{Parser, Subcommand};
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{Device, Host};
+use cpal::traits::{DeviceTrait, HostTrait};
+use cpal::{Device, Host, SampleRate};
+use vban::error::io_error;
+use vban::service::{device_type, features};
+use vban::{
+    AudioSink, AudioSource, ChannelMap, DataFormat, DeviceSink, DeviceSource, FileSource,
+    GeneratorSource, Identity, Interface, JitterConfig, NullSink, ReceiverConfig, RecordConfig,
+    ResamplerQuality, SenderConfig, SerialFormat, SerialListener, SerialSender, SerialSenderConfig,
+    Target, TextFormat, TextListener, TextSender, TextSenderConfig, VbanError, VbanReceiver,
+    VbanSender, Waveform, device, target,
+};
 
 #[derive(Debug, clap::Args)]
 struct ReceiverArgs {
@@ -13,9 +26,61 @@ struct ReceiverArgs {
     #[arg(short, long, default_value_t = String::from("default"))]
     output_device: String,
 
-    /// The address to bind the UDP socket to
+    /// The address to bind the UDP socket to, `[::]:PORT` listens on IPv4
+    /// and IPv6
     #[arg(long)]
     bind_address: SocketAddr,
+
+    /// A multicast group to join, e.g. `239.1.2.3`
+    #[arg(long)]
+    multicast_group: Option<IpAddr>,
+
+    /// The interface to join the multicast group on, as its IPv4 address or,
+    /// for IPv6 groups, its index
+    #[arg(long, requires = "multicast_group")]
+    interface: Option<Interface>,
+
+    /// Only play the stream with this name
+    #[arg(long)]
+    stream_name: Option<String>,
+
+    /// Only play streams sent from this IP address
+    #[arg(long)]
+    source: Option<IpAddr>,
+
+    /// How stream channels are routed to device channels, e.g. `0,1->2,3`
+    #[arg(long)]
+    channel_map: Option<ChannelMap>,
+
+    /// The gain to mix a stream with, as `NAME=GAIN`, can be repeated
+    #[arg(long, value_parser = parse_gain)]
+    gain: Vec<(String, f32)>,
+
+    /// The buffered delay in milliseconds below which playback pauses to refill
+    #[arg(long, default_value_t = 0.0)]
+    min_latency: f32,
+
+    /// The buffered delay in milliseconds above which audio is dropped,
+    /// defaults to four times the latency, or at least a packet
+    #[arg(long, value_parser = parse_latency)]
+    max_latency: Option<f32>,
+
+    /// Record every stream to numbered files named after this path, FLAC
+    /// for a `.flac` path and WAV (RF64 past 4 GiB) otherwise
+    #[arg(long)]
+    record: Option<PathBuf>,
+
+    /// Start a new recording file after this many megabytes
+    #[arg(long, requires = "record")]
+    record_max_size: Option<u64>,
+
+    /// Start a new recording file after this many seconds
+    #[arg(long, requires = "record")]
+    record_max_duration: Option<u64>,
+
+    /// Only record, without playing anything
+    #[arg(long, requires = "record")]
+    no_output: bool,
 }
 
 #[derive(Debug, clap::Args)]
@@ -24,19 +89,221 @@ struct TransmitterArgs {
     #[arg(short, long, default_value_t = String::from("default"))]
     input_device: String,
 
-    /// The target to send audio data to
+    /// A WAV, FLAC or Ogg Vorbis file to send instead of capturing from a
+    /// device, paced in real time
+    #[arg(long, conflicts_with = "input_device")]
+    input_file: Option<PathBuf>,
+
+    /// Start the input file over when it ends instead of exiting
+    #[arg(long = "loop", requires = "input_file")]
+    looping: bool,
+
+    /// A target to send audio data to as `HOST:PORT`, can be repeated.
+    /// Overrides can follow, e.g. `mixer.local:6980,name=Mic,format=i16`
+    #[arg(long, required_unless_present = "targets_file")]
+    target: Vec<Target>,
+
+    /// A file listing more targets, one per line
+    #[arg(long)]
+    targets_file: Option<PathBuf>,
+
+    /// The address to send from, defaults to any address of the target's
+    /// family
+    #[arg(long)]
+    bind_address: Option<SocketAddr>,
+
+    /// The name of the stream, at most 16 bytes
+    #[arg(long, default_value_t = String::from("Stream1"))]
+    stream_name: String,
+
+    /// The sample rate to send at, defaults to the device rate, or 48000 Hz
+    /// without a sound card
+    #[arg(long)]
+    stream_rate: Option<u32>,
+
+    /// The sample format to send
+    #[arg(long, value_enum, default_value_t = DataFormat::F32)]
+    format: DataFormat,
+
+    /// The number of channels to send, defaults to the device channel count,
+    /// or 2 without a sound card
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..=256))]
+    channels: Option<u16>,
+
+    /// How device channels are routed to sent channels, e.g. `0,1->2,3`
+    #[arg(long)]
+    channel_map: Option<ChannelMap>,
+
+    /// Samples per channel in every packet, defaults to as many as fit
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..=256))]
+    samples_per_packet: Option<u16>,
+
+    /// The signal the generator backend sends
+    #[arg(long, value_enum, default_value_t = Waveform::default())]
+    signal: Waveform,
+
+    /// The frequency of the sine the generator backend sends in Hz
+    #[arg(long, default_value_t = 440.0)]
+    frequency: f32,
+
+    /// How many routers multicast packets may cross
+    #[arg(long, default_value_t = 1)]
+    multicast_ttl: u32,
+}
+
+#[derive(Debug, clap::Args)]
+struct TextSendArgs {
+    /// The target to send the text to as `HOST:PORT`
+    #[arg(long)]
+    target: Target,
+
+    /// The name of the stream, Voicemeeter listens to `Command1`
+    #[arg(long, default_value_t = String::from("Command1"))]
+    stream_name: String,
+
+    /// The character encoding to send
+    #[arg(long, value_enum, default_value_t = TextFormat::default())]
+    format: TextFormat,
+
+    /// The bit rate announced in the header, 0 leaving it unspecified
+    #[arg(long, default_value_t = 0)]
+    bit_rate: u32,
+
+    /// The address to send from, defaults to any address of the target's
+    /// family
+    #[arg(long)]
+    bind_address: Option<SocketAddr>,
+
+    /// The text to send, e.g. `Strip[0].Mute = 1;`, every line read from
+    /// stdin if not given
+    text: Option<String>,
+}
+
+#[derive(Debug, clap::Args)]
+struct TextListenArgs {
+    /// The address to bind the UDP socket to
+    #[arg(long)]
+    bind_address: SocketAddr,
+
+    /// Only accept text from the stream with this name
+    #[arg(long)]
+    stream_name: Option<String>,
+
+    /// Only accept text sent from this IP address
+    #[arg(long)]
+    source: Option<IpAddr>,
+
+    /// A program to run with the received text as its argument, instead of
+    /// printing it
+    #[arg(long)]
+    exec: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+enum TextCommands {
+    /// Send text commands
+    Send(TextSendArgs),
+    /// Print or dispatch received text
+    Listen(TextListenArgs),
+}
+
+#[derive(Debug, clap::Args)]
+struct TextArgs {
+    #[clap(subcommand)]
+    command: TextCommands,
+}
+
+#[derive(Debug, clap::Args)]
+#[command(group(clap::ArgGroup::new("readable").args(["device", "input"])))]
+#[command(group(clap::ArgGroup::new("writable").args(["device", "output"])))]
+struct SerialArgs {
+    /// The serial port to bridge, e.g. `/dev/ttyUSB0`, read from and written
+    /// to
+    #[arg(long, requires = "baud_rate")]
+    device: Option<String>,
+
+    /// The baud rate to open the serial port at
+    #[arg(long, requires = "device")]
+    baud_rate: Option<u32>,
+
+    /// A file or FIFO to read the data to send from, in place of a serial
+    /// port. Reading and writing one FIFO would send back everything
+    /// received, so the two directions take separate paths.
+    #[arg(long, requires = "target")]
+    input: Option<PathBuf>,
+
+    /// A file or FIFO to append the received data to, in place of a serial
+    /// port
+    #[arg(long, requires = "bind_address")]
+    output: Option<PathBuf>,
+
+    /// Where to send the data read from the device as `HOST:PORT`
+    #[arg(long, required_unless_present = "bind_address", requires = "readable")]
+    target: Option<Target>,
+
+    /// The address to receive the data written to the device on
+    #[arg(long, requires = "writable")]
+    bind_address: Option<SocketAddr>,
+
+    /// The name of the stream sent, and the only one received
+    #[arg(long, default_value_t = String::from("Serial1"))]
+    stream_name: String,
+
+    /// What the stream carries
+    #[arg(long, value_enum, default_value_t = SerialFormat::default())]
+    format: SerialFormat,
+
+    /// The channel of the stream sent
+    #[arg(long, default_value_t = 0)]
+    channel: u8,
+
+    /// Only accept data sent from this IP address
     #[arg(long)]
+    source: Option<IpAddr>,
+}
+
+#[derive(Debug, clap::Args)]
+struct DiscoverArgs {
+    /// Where to send the ping, the broadcast address of the local network
+    /// by default
+    #[arg(long, default_value = "255.255.255.255:6980")]
     target: SocketAddr,
+
+    /// How long to wait for replies in milliseconds
+    #[arg(long, default_value_t = 1000)]
+    timeout: u64,
 }
 
 #[derive(Subcommand, Debug)]
 enum Commands {
     Receiver(ReceiverArgs),
     Transmitter(TransmitterArgs),
+    /// Send or receive VBAN-TEXT, used to remote control Voicemeeter
+    Text(TextArgs),
+    /// Bridge a serial device or FIFO to VBAN-SERIAL in both directions
+    Serial(SerialArgs),
+    /// List the VBAN devices answering a ping
+    Discover(DiscoverArgs),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Backend {
+    /// Sound cards through the system audio API
+    Cpal,
+    /// No sound card: received audio is discarded and silence is sent,
+    /// paced by the sample rate
+    Null,
+    /// No sound card: received audio is discarded and the test signal picked
+    /// with `--signal` is sent
+    Generator,
 }
 
 #[derive(Debug, clap::Args)]
 struct GlobalArgs {
+    /// Where audio is played and captured
+    #[arg(long, value_enum, default_value_t = Backend::Cpal)]
+    backend: Backend,
+
     /// Whether to list available input devices
     #[arg(long, default_value_t = false)]
     list_inputs: bool,
@@ -49,13 +316,17 @@ struct GlobalArgs {
     #[arg(long, default_value_t = false)]
     list_configs: bool,
 
-    /// The delay in milliseconds
-    #[arg(short, long, default_value_t = 10.0)]
+    /// The delay in milliseconds the receiver buffers to absorb network jitter
+    #[arg(short, long, default_value_t = 10.0, value_parser = parse_latency)]
     latency: f32,
+
+    /// The sample rate conversion preset, trading quality for latency
+    #[arg(long, value_enum, default_value_t = ResamplerQuality::default())]
+    resampler: ResamplerQuality,
 }
 
 #[derive(Parser, Debug)]
-#[command(author, version, about = "cross-platform vban", long_about = None)]
+#[command(author, version, about = "cross-platform vban", long_about = None, after_help = EXIT_CODES)]
 struct Args {
     #[clap(flatten)]
     global_args: GlobalArgs,
@@ -64,71 +335,103 @@ struct Args {
     command: Option<Commands>,
 }
 
+const EXIT_CODES: &str = "Exit codes:
+  1  other errors
+  2  invalid arguments
+  3  audio device not found
+  4  unsupported configuration
+  5  socket could not be bound
+  6  malformed packet
+  7  audio stream error
+  8  I/O error";
+
+/// The exit code for `err`, as listed in `EXIT_CODES`
+fn exit_code(err: &anyhow::Error) -> i32 {
+    match err.downcast_ref::<VbanError>() {
+        Some(VbanError::DeviceNotFound(_)) => 3,
+        Some(VbanError::UnsupportedConfig(_)) => 4,
+        Some(VbanError::SocketBind(..)) => 5,
+        Some(VbanError::MalformedPacket(_)) => 6,
+        Some(VbanError::Stream(_)) => 7,
+        Some(VbanError::Io(_)) => 8,
+        None => 1,
+    }
+}
+
+/// The name of `device`, for messages, which some backends fail to report
+fn device_name(device: &Device) -> String {
+    device
+        .name()
+        .unwrap_or_else(|_| String::from("unnamed device"))
+}
+
+fn parse_gain(s: &str) -> anyhow::Result<(String, f32)> {
+    let (name, gain) = s
+        .rsplit_once('=')
+        .ok_or_else(|| anyhow::anyhow!("Expected NAME=GAIN, got '{}'", s))?;
+    Ok((name.to_string(), gain.parse()?))
+}
+
+fn parse_latency(s: &str) -> anyhow::Result<f32> {
+    let ms: f32 = s.parse()?;
+    if !(ms > 0.0 && ms.is_finite()) {
+        anyhow::bail!("Expected a positive number of milliseconds, got '{}'", s);
+    }
+    Ok(ms)
+}
+
+fn millis(ms: f32) -> Duration {
+    Duration::from_secs_f32(ms.max(0.0) / 1000.0)
+}
+
 fn receiver(
     host: &Host,
     global_args: GlobalArgs,
     receiver_args: ReceiverArgs,
 ) -> anyhow::Result<()> {
-    let output_device = if receiver_args.output_device == "default" {
-        host.default_output_device()
+    let output: Option<Box<dyn AudioSink>> = if receiver_args.no_output {
+        None
+    } else if global_args.backend != Backend::Cpal {
+        Some(Box::new(NullSink::new(2)))
     } else {
-        host.input_devices()?.find(|x| {
-            x.name()
-                .map(|y| y == receiver_args.output_device)
-                .unwrap_or(false)
-        })
-    }
-    .expect("Failed to find output device");
-
-    println!("Using \"{}\" output device.", output_device.name().unwrap());
-    if global_args.list_configs {
-        println!("Supported configs:");
-        output_device
-            .supported_output_configs()
-            .unwrap()
-            .for_each(|x| println!("\t{:?}", x));
-        return Ok(());
-    }
-
-    let mut config = output_device.default_output_config().unwrap();
-    config.sample_format();
-
-    let socket = UdpSocket::bind(&receiver_args.bind_address).unwrap();
-    let (tx, rx) = mpsc::channel();
-
-    thread::spawn(move || {
-        let mut buffer = [0u8; 4096];
-        loop {
-            if let Ok((amt, _)) = socket.recv_from(&mut buffer) {
-                let samples: Vec<f32> = buffer[..amt]
-                    .chunks_exact(4)
-                    .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
-                    .collect();
-                let _ = tx.send(samples);
-            }
-            // println!("New packet!");
+        let output_device = device::find_output_device(host, &receiver_args.output_device)?;
+        println!("Using \"{}\" output device.", device_name(&output_device));
+        if global_args.list_configs {
+            println!("Supported configs:");
+            output_device
+                .supported_output_configs()
+                .map_err(VbanError::from)?
+                .for_each(|x| println!("\t{:?}", x));
+            return Ok(());
         }
-    });
+        Some(Box::new(DeviceSink::new(output_device)))
+    };
 
-    let output_data_fn = move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-        if let Ok(samples) = rx.try_recv() {
-            for (d, s) in data.iter_mut().zip(samples.iter()) {
-                *d = *s;
+    let config = ReceiverConfig {
+        multicast_group: receiver_args.multicast_group,
+        interface: receiver_args.interface,
+        stream_name: receiver_args.stream_name,
+        source: receiver_args.source,
+        channel_map: receiver_args.channel_map,
+        gains: receiver_args.gain.into_iter().collect(),
+        resampler_quality: global_args.resampler,
+        jitter: {
+            let default = JitterConfig::new(millis(global_args.latency));
+            JitterConfig {
+                min: millis(receiver_args.min_latency),
+                max: receiver_args.max_latency.map_or(default.max, millis),
+                ..default
             }
-            // println!("Output some real data");
-        } else {
-            data.fill(0.0);
-            // println!("Output some FAKE data");
-        }
+        },
+        identity: Some(Identity::local(device_type::RECEPTOR, features::AUDIO)),
+        record: receiver_args.record.map(|path| RecordConfig {
+            max_size: receiver_args.record_max_size.map(|x| x * 1_000_000),
+            max_duration: receiver_args.record_max_duration.map(Duration::from_secs),
+            ..RecordConfig::new(path)
+        }),
+        ..ReceiverConfig::new(receiver_args.bind_address)
     };
-
-    let output_stream = output_device.build_output_stream(
-        &config.into(),
-        output_data_fn,
-        |err| eprintln!("Stream error: {}", err),
-        None,
-    )?;
-    output_stream.play()?;
+    let _receiver = VbanReceiver::start(output, config)?;
 
     loop {
         thread::sleep(Duration::from_secs(1));
@@ -136,130 +439,292 @@ fn receiver(
 }
 
 fn transmitter(host: &Host, global_args: GlobalArgs, args: TransmitterArgs) -> anyhow::Result<()> {
-    let input_device = if args.input_device == "default" {
-        host.default_input_device()
-    } else {
-        host.input_devices()?
-            .find(|x| x.name().map(|y| y == args.input_device).unwrap_or(false))
+    let mut targets = args.target;
+    if let Some(path) = &args.targets_file {
+        targets.extend(target::read_targets(path)?);
     }
-    .expect("Failed to find input device");
-
-    println!("Using \"{}\" input device.", input_device.name().unwrap());
 
-    if global_args.list_configs {
-        println!("Supported configs:");
-        input_device
-            .supported_input_configs()
-            .unwrap()
-            .for_each(|x| println!("\t{:?}", x));
-        return Ok(());
-    }
+    let config = SenderConfig {
+        stream_name: args.stream_name,
+        stream_rate: args.stream_rate.map(SampleRate),
+        data_format: args.format,
+        channels: args.channels,
+        channel_map: args.channel_map,
+        samples_per_packet: args.samples_per_packet.map(usize::from),
+        bind_address: args.bind_address,
+        multicast_ttl: args.multicast_ttl,
+        resampler_quality: global_args.resampler,
+        ..SenderConfig::new(targets)
+    };
 
-    let mut config = input_device.default_input_config().unwrap();
+    let sender = match &args.input_file {
+        Some(path) => {
+            let source = FileSource::open(path, args.looping)?;
+            let rate = source.sample_rate();
+            let sender = VbanSender::start(source, config)?;
+            println!("Sending \"{}\" at {} Hz.", path.display(), rate.0);
+            sender
+        }
+        None if global_args.backend != Backend::Cpal => {
+            let rate = SampleRate(args.stream_rate.unwrap_or(48000));
+            let channels = args.channels.unwrap_or(2) as usize;
+            let signal = match global_args.backend {
+                Backend::Generator => args.signal,
+                _ => Waveform::Silence,
+            };
+            let source = GeneratorSource::new(signal, args.frequency, rate, channels);
+            let sender = VbanSender::start(source, config)?;
+            println!("Sending a generated {:?} signal at {} Hz.", signal, rate.0);
+            sender
+        }
+        None => {
+            let input_device = device::find_input_device(host, &args.input_device)?;
+            println!("Using \"{}\" input device.", device_name(&input_device));
 
-    let (tx, rx) = mpsc::channel();
+            if global_args.list_configs {
+                println!("Supported configs:");
+                input_device
+                    .supported_input_configs()
+                    .map_err(VbanError::from)?
+                    .for_each(|x| println!("\t{:?}", x));
+                return Ok(());
+            }
+            VbanSender::start(DeviceSource::new(input_device)?, config)?
+        }
+    };
 
-    let nb_channels = config.channels();
-    let input_data_fn = move |data: &[f32], _: &cpal::InputCallbackInfo| {
-        let mut stereo_data = Vec::with_capacity(data.len() * 2);
+    // only a file source ever ends
+    while !sender.is_finished() {
+        thread::sleep(Duration::from_millis(100));
+    }
+    Ok(())
+}
 
-        // If the input is mono (1 channel), duplicate each sample
-        if nb_channels == 1 {
-            for &sample in data {
-                stereo_data.push(sample);
-                stereo_data.push(sample);
+fn text_send(args: TextSendArgs) -> anyhow::Result<()> {
+    let config = TextSenderConfig {
+        stream_name: args.stream_name,
+        bit_rate: args.bit_rate,
+        format: args.format,
+        bind_address: args.bind_address,
+        ..TextSenderConfig::new(args.target)
+    };
+    let mut sender = TextSender::new(config)?;
+    match args.text {
+        Some(text) => sender.send(&text)?,
+        None => {
+            for line in io::stdin().lock().lines() {
+                sender.send(&line.map_err(|err| io_error(err, "Failed to read stdin"))?)?;
             }
-            let _ = tx.send(stereo_data);
-        } else {
-            let _ = tx.send(data.to_vec());
         }
-    };
+    }
+    Ok(())
+}
 
-    let input_stream = input_device.build_input_stream(
-        &config.into(),
-        input_data_fn,
-        |err| eprintln!("An error occurred on stream: {}", err),
-        None,
-    )?;
-    input_stream.play()?;
+fn text_listen(args: TextListenArgs) -> anyhow::Result<()> {
+    let mut listener = TextListener::bind(args.bind_address)?;
+    loop {
+        let (source, packet) = listener.recv()?;
+        if args.source.is_some_and(|x| x != source.ip())
+            || args
+                .stream_name
+                .as_ref()
+                .is_some_and(|x| *x != packet.stream_name)
+        {
+            continue;
+        }
+        match &args.exec {
+            Some(program) => match process::Command::new(program).arg(&packet.text).status() {
+                Ok(status) if !status.success() => {
+                    eprintln!("{} exited with {}", program.display(), status)
+                }
+                Ok(_) => {}
+                Err(err) => eprintln!("Failed to run {}: {}", program.display(), err),
+            },
+            None => println!("{} \"{}\": {}", source, packet.stream_name, packet.text),
+        }
+    }
+}
 
-    let socket = UdpSocket::bind("0.0.0.0:0")?;
-    socket.connect(args.target)?;
+fn serial(args: SerialArgs) -> anyhow::Result<()> {
+    // a serial port has separate lines for each direction, files and FIFOs
+    // are opened for one direction only
+    let mut reader: Option<Box<dyn Read + Send>> = None;
+    let mut writer: Option<Box<dyn Write + Send>> = None;
+    if let (Some(device), Some(baud_rate)) = (&args.device, args.baud_rate) {
+        let port_error = |err: serialport::Error| {
+            io_error(err.into(), format_args!("Failed to open '{}'", device))
+        };
+        let port = serialport::new(device, baud_rate)
+            .timeout(Duration::from_millis(100))
+            .open()
+            .map_err(port_error)?;
+        reader = Some(port.try_clone().map_err(port_error)?);
+        writer = Some(port);
+    }
+    if let Some(path) = &args.input {
+        let file = File::open(path)
+            .map_err(|err| io_error(err, format_args!("Failed to open '{}'", path.display())))?;
+        reader = Some(Box::new(file));
+    }
+    let input_name = match (&args.device, &args.input) {
+        (Some(device), _) => device.clone(),
+        (None, Some(path)) => path.display().to_string(),
+        (None, None) => String::new(),
+    };
 
-    thread::spawn(move || {
+    let listener = match args.bind_address {
+        Some(bind_address) => {
+            let mut listener = SerialListener::bind(bind_address)?;
+            let stream_name = args.stream_name.clone();
+            let output = args.output.clone();
+            let device = args.device.clone();
+            Some(thread::spawn(move || -> anyhow::Result<()> {
+                // opening a FIFO for writing waits for its reader, so it is
+                // done here rather than holding up the sending side
+                let (mut writer, output_name) = match (writer.take(), device, output) {
+                    (Some(writer), Some(device), _) => (writer, device),
+                    (None, _, Some(path)) => {
+                        let name = path.display().to_string();
+                        let file = OpenOptions::new()
+                            .create(true)
+                            .append(true)
+                            .open(path)
+                            .map_err(|err| {
+                                io_error(err, format_args!("Failed to open '{}'", name))
+                            })?;
+                        (Box::new(file) as Box<dyn Write + Send>, name)
+                    }
+                    _ => unreachable!("clap requires a device or an output"),
+                };
+                loop {
+                    let (source, packet) = listener.recv()?;
+                    if args.source.is_none_or(|x| x == source.ip())
+                        && packet.stream_name == stream_name
+                    {
+                        writer
+                            .write_all(&packet.data)
+                            .and_then(|_| writer.flush())
+                            .map_err(|err| {
+                                io_error(err, format_args!("Failed to write '{}'", output_name))
+                            })?;
+                    }
+                }
+            }))
+        }
+        None => None,
+    };
+
+    if let (Some(target), Some(mut reader)) = (args.target, reader) {
+        let config = SerialSenderConfig {
+            stream_name: args.stream_name,
+            bit_rate: args.baud_rate.unwrap_or(0),
+            channel: args.channel,
+            format: args.format,
+            ..SerialSenderConfig::new(target)
+        };
+        let mut sender = SerialSender::new(config)?;
+        let mut buffer = [0u8; 1024];
         loop {
-            if let Ok(buffer) = rx.recv() {
-                let packet: Vec<u8> = buffer.iter().flat_map(|s| s.to_le_bytes()).collect();
-                let _ = socket.send(&packet);
+            match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(amt) => sender.send(&buffer[..amt])?,
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::TimedOut | io::ErrorKind::Interrupted
+                    ) => {}
+                Err(err) => {
+                    return Err(
+                        io_error(err, format_args!("Failed to read '{}'", input_name)).into(),
+                    );
+                }
             }
         }
-    });
+    }
 
-    loop {
-        thread::sleep(Duration::from_secs(1));
+    match listener {
+        Some(thread) => thread
+            .join()
+            .map_err(|_| anyhow::anyhow!("Serial listener panicked"))?,
+        None => Ok(()),
     }
 }
-// TODO: parse the vban network stream config
-// https://vb-audio.com/Voicemeeter/VBANProtocol_Specifications.pdf
-// TODO: use the VBAN header in network communication.
 
-// TODO: use rust rubato for converting between sample rates
-// https://github.com/HEnquist/rubato
-
-// TODO: handle different different types of samples(i24,i32,f32)
-// https://github.com/RustAudio/cpal/blob/master/examples/beep.rs
+fn discover(args: DiscoverArgs) -> anyhow::Result<()> {
+    let identity = Identity::local(device_type::RECEPTOR, features::AUDIO);
+    let peers = vban::discover(args.target, &identity, Duration::from_millis(args.timeout))?;
+    if peers.is_empty() {
+        println!("No VBAN devices answered.");
+    }
+    for (address, peer) in peers {
+        println!(
+            "{}\t\"{}\" {} on {} ({})",
+            address, peer.device_name, peer.application_name, peer.host_name, peer.user_name
+        );
+    }
+    Ok(())
+}
 
-// TODO: handle different amounts of channels
+/// Prints every device `devices` yields, with the configs `configs` lists
+/// for it when asked to
+fn print_devices<C: fmt::Debug>(
+    devices: impl Iterator<Item = Device>,
+    list_configs: bool,
+    configs: impl Fn(&Device) -> Result<Vec<C>, cpal::SupportedStreamConfigsError>,
+) {
+    for device in devices {
+        if !list_configs {
+            println!("\t{}", device_name(&device));
+            continue;
+        }
+        let configs: Vec<String> = match configs(&device) {
+            Ok(configs) => configs.iter().map(|x| format!("{:?}", x)).collect(),
+            Err(err) => vec![format!("Failed to list configs: {}", err)],
+        };
+        println!(
+            "\t\"{}\"\n\t\t{}",
+            device_name(&device),
+            configs.join(",\n\t\t")
+        )
+    }
+}
 
 // TODO: consider using tauri+vuejs+nuxt_ui to create an app that incorporates these features
 // https://www.reddit.com/r/tauri/comments/1cxawd1/preventing_the_web_process_from_pausing_while_in/
-fn main() -> anyhow::Result<()> {
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("Error: {}", err);
+        process::exit(exit_code(&err));
+    }
+}
+
+fn run() -> anyhow::Result<()> {
     let args = Args::parse();
     let global_args = args.global_args;
     let host = cpal::default_host();
 
+    if global_args.list_configs && global_args.backend != Backend::Cpal {
+        return Err(VbanError::UnsupportedConfig(String::from(
+            "--list-configs lists sound card configs, which only the cpal backend uses",
+        ))
+        .into());
+    }
     if global_args.list_inputs {
         println!("Available input devices:");
-        let print_fn = |device: &Device| match global_args.list_configs {
-            true => {
-                let configs: Vec<String> = device
-                    .supported_input_configs()
-                    .unwrap()
-                    .into_iter()
-                    .map(|x| format!("{:?}", x))
-                    .collect();
-                println!(
-                    "\t\"{}\"\n\t\t{}",
-                    device.name().unwrap(),
-                    configs.join(",\n\t\t")
-                )
-            }
-            false => println!("\t{}", device.name().unwrap()),
-        };
-        host.input_devices().unwrap().for_each(|dev| print_fn(&dev));
+        print_devices(
+            host.input_devices().map_err(VbanError::from)?,
+            global_args.list_configs,
+            |device| Ok(device.supported_input_configs()?.collect()),
+        );
         return Ok(());
     }
     if global_args.list_outputs {
         println!("Available output devices:");
-        let print_fn = |device: &Device| match global_args.list_configs {
-            true => {
-                let configs: Vec<String> = device
-                    .supported_output_configs()
-                    .unwrap()
-                    .into_iter()
-                    .map(|x| format!("{:?}", x))
-                    .collect();
-                println!(
-                    "\t\"{}\"\n\t\t{}",
-                    device.name().unwrap(),
-                    configs.join(",\n\t\t")
-                )
-            }
-            false => println!("\t{}", device.name().unwrap()),
-        };
-        host.output_devices()
-            .unwrap()
-            .for_each(|dev| print_fn(&dev));
+        print_devices(
+            host.output_devices().map_err(VbanError::from)?,
+            global_args.list_configs,
+            |device| Ok(device.supported_output_configs()?.collect()),
+        );
         return Ok(());
     }
 
@@ -267,10 +732,16 @@ fn main() -> anyhow::Result<()> {
         Some(cmd) => cmd,
         None => return Ok(()),
     };
-    return match command {
+    match command {
         Commands::Receiver(receiver_args) => receiver(&host, global_args, receiver_args),
         Commands::Transmitter(transmitter_args) => {
             transmitter(&host, global_args, transmitter_args)
         }
-    };
+        Commands::Text(text_args) => match text_args.command {
+            TextCommands::Send(args) => text_send(args),
+            TextCommands::Listen(args) => text_listen(args),
+        },
+        Commands::Serial(serial_args) => serial(serial_args),
+        Commands::Discover(discover_args) => discover(discover_args),
+    }
 }
@@ -0,0 +1,67 @@
+// Mixing of several received streams into one output stream
+use std::sync::mpsc;
+
+use cpal::SampleRate;
+
+use crate::error::Result;
+use crate::jitter::JitterConsumer;
+use crate::sink::{AudioSink, SinkStream};
+
+enum Command {
+    Add(u64, JitterConsumer, f32),
+    Remove(u64),
+}
+
+/// An output stream summing the jitter buffers of all active streams, each
+/// scaled by its gain
+pub struct Mixer {
+    commands: mpsc::Sender<Command>,
+    stream: SinkStream,
+}
+
+impl Mixer {
+    /// Opens `sink` at `rate` if it supports it, at the rate it picks
+    /// otherwise
+    pub fn open(sink: &dyn AudioSink, rate: SampleRate) -> Result<Self> {
+        let (commands, rx) = mpsc::channel();
+
+        let mut inputs: Vec<(u64, JitterConsumer, f32)> = Vec::new();
+        let mut scratch = Vec::new();
+        let output_data_fn = move |data: &mut [f32]| {
+            while let Ok(command) = rx.try_recv() {
+                match command {
+                    Command::Add(id, consumer, gain) => inputs.push((id, consumer, gain)),
+                    Command::Remove(id) => inputs.retain(|(x, _, _)| *x != id),
+                }
+            }
+            data.fill(0.0);
+            scratch.resize(data.len(), 0.0);
+            for (_, consumer, gain) in &mut inputs {
+                consumer.fill(&mut scratch);
+                for (d, s) in data.iter_mut().zip(&scratch) {
+                    *d += s * *gain;
+                }
+            }
+        };
+
+        let stream = sink.open(rate, Box::new(output_data_fn))?;
+        Ok(Self { commands, stream })
+    }
+
+    pub fn rate(&self) -> SampleRate {
+        self.stream.rate()
+    }
+
+    pub fn channels(&self) -> usize {
+        self.stream.channels()
+    }
+
+    /// Starts mixing in the audio from `consumer`
+    pub fn add(&self, id: u64, consumer: JitterConsumer, gain: f32) {
+        let _ = self.commands.send(Command::Add(id, consumer, gain));
+    }
+
+    pub fn remove(&self, id: u64) {
+        let _ = self.commands.send(Command::Remove(id));
+    }
+}
@@ -0,0 +1,345 @@
+// UDP socket setup for unicast, broadcast and multicast streams
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::str::FromStr;
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::error::{Result, VbanError, unsupported};
+
+/// The network interface to join a multicast group on, given by its IPv4
+/// address for IPv4 groups or by its index for IPv6 groups
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interface {
+    Address(Ipv4Addr),
+    Index(u32),
+}
+
+impl FromStr for Interface {
+    type Err = VbanError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Ok(address) = s.parse() {
+            Ok(Interface::Address(address))
+        } else if let Ok(index) = s.parse() {
+            Ok(Interface::Index(index))
+        } else {
+            unsupported!(
+                "Expected an IPv4 address or an interface index, got '{}'",
+                s
+            )
+        }
+    }
+}
+
+/// Receive buffer asked for, so that bursts of the many small packets of
+/// wide streams are not dropped before the receiver gets to them
+const RECEIVE_BUFFER_SIZE: usize = 1 << 20;
+
+/// Binds a socket for receiving, joining `multicast_group` on `interface`
+/// (any interface if not set) when given. Bound to the unspecified IPv6
+/// address, the socket receives IPv4 packets too, from IPv4-mapped addresses.
+pub(crate) fn receiver_socket(
+    bind_address: SocketAddr,
+    multicast_group: Option<IpAddr>,
+    interface: Option<Interface>,
+) -> Result<UdpSocket> {
+    match (multicast_group, interface) {
+        (None, Some(_)) => unsupported!("An interface is only used with a multicast group"),
+        (Some(group), _) if !group.is_multicast() => {
+            unsupported!("{} is not a multicast address", group)
+        }
+        (Some(group), _) if group.is_ipv4() != bind_address.is_ipv4() => unsupported!(
+            "Cannot join multicast group {} on a socket bound to {}",
+            group,
+            bind_address
+        ),
+        (Some(group @ IpAddr::V4(_)), Some(interface @ Interface::Index(_)))
+        | (Some(group @ IpAddr::V6(_)), Some(interface @ Interface::Address(_))) => {
+            unsupported!("Interface {:?} cannot be used to join {}", interface, group)
+        }
+        _ => {}
+    }
+    bind_receiver(bind_address, multicast_group, interface)
+        .map_err(|err| VbanError::SocketBind(bind_address, err))
+}
+
+/// The socket setup of `receiver_socket`, once its arguments are known to fit
+/// together
+fn bind_receiver(
+    bind_address: SocketAddr,
+    multicast_group: Option<IpAddr>,
+    interface: Option<Interface>,
+) -> io::Result<UdpSocket> {
+    let socket = Socket::new(
+        Domain::for_address(bind_address),
+        Type::DGRAM,
+        Some(Protocol::UDP),
+    )?;
+    if bind_address.ip() == IpAddr::V6(Ipv6Addr::UNSPECIFIED) {
+        socket.set_only_v6(false)?;
+    }
+    if multicast_group.is_some() {
+        // let several receivers on this host join the same group
+        socket.set_reuse_address(true)?;
+    }
+    // the system may cap the size, which is still better than the default
+    let _ = socket.set_recv_buffer_size(RECEIVE_BUFFER_SIZE);
+    socket.bind(&bind_address.into())?;
+
+    match (multicast_group, interface) {
+        (Some(IpAddr::V4(group)), None) => {
+            socket.join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED)?
+        }
+        (Some(IpAddr::V4(group)), Some(Interface::Address(interface))) => {
+            socket.join_multicast_v4(&group, &interface)?
+        }
+        (Some(IpAddr::V6(group)), None) => socket.join_multicast_v6(&group, 0)?,
+        (Some(IpAddr::V6(group)), Some(Interface::Index(index))) => {
+            socket.join_multicast_v6(&group, index)?
+        }
+        _ => {}
+    }
+    Ok(socket.into())
+}
+
+/// Sockets for sending to IPv4 and IPv6 targets, one per address family,
+/// bound when first needed
+pub(crate) struct SenderSockets {
+    bind_address: Option<SocketAddr>,
+    multicast_ttl: u32,
+    v4: Option<UdpSocket>,
+    v6: Option<UdpSocket>,
+}
+
+impl SenderSockets {
+    /// Sockets bound to `bind_address`, or to an ephemeral port of the
+    /// target's family if not set, with multicast packets crossing at most
+    /// `multicast_ttl` routers
+    pub(crate) fn new(bind_address: Option<SocketAddr>, multicast_ttl: u32) -> Self {
+        Self {
+            bind_address,
+            multicast_ttl,
+            v4: None,
+            v6: None,
+        }
+    }
+
+    /// The socket to send to `target` from, binding it if needed
+    pub(crate) fn socket_for(&mut self, target: SocketAddr) -> Result<&UdpSocket> {
+        if let Some(bind_address) = self.bind_address
+            && bind_address.is_ipv4() != target.is_ipv4()
+        {
+            unsupported!("Cannot send to {} from {}", target, bind_address);
+        }
+        let bind_address = self.bind_address.unwrap_or(match target {
+            SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+        });
+        let socket = match target {
+            SocketAddr::V4(_) => &mut self.v4,
+            SocketAddr::V6(_) => &mut self.v6,
+        };
+        if socket.is_none() {
+            *socket = Some(
+                sender_socket(bind_address, self.multicast_ttl)
+                    .map_err(|err| VbanError::SocketBind(bind_address, err))?,
+            );
+        }
+        Ok(socket.as_ref().unwrap())
+    }
+
+    pub(crate) fn send_to(&mut self, packet: &[u8], target: SocketAddr) -> Result<()> {
+        self.socket_for(target)?.send_to(packet, target)?;
+        Ok(())
+    }
+}
+
+/// Binds a socket for sending that may address broadcast and multicast
+/// targets
+fn sender_socket(bind_address: SocketAddr, multicast_ttl: u32) -> io::Result<UdpSocket> {
+    let socket = Socket::new(
+        Domain::for_address(bind_address),
+        Type::DGRAM,
+        Some(Protocol::UDP),
+    )?;
+    if bind_address.is_ipv4() {
+        socket.set_broadcast(true)?;
+        socket.set_multicast_ttl_v4(multicast_ttl)?;
+    } else {
+        socket.set_multicast_hops_v6(multicast_ttl)?;
+    }
+    socket.bind(&bind_address.into())?;
+    Ok(socket.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn receive(socket: &UdpSocket) -> (Vec<u8>, SocketAddr) {
+        socket
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let mut buffer = [0u8; 64];
+        let (amt, source) = socket.recv_from(&mut buffer).unwrap();
+        (buffer[..amt].to_vec(), source)
+    }
+
+    #[test]
+    fn sends_over_ipv6_loopback() {
+        let receiver = receiver_socket("[::1]:0".parse().unwrap(), None, None).unwrap();
+        let target = receiver.local_addr().unwrap();
+        let mut sockets = SenderSockets::new(None, 1);
+        sockets.send_to(b"VBAN", target).unwrap();
+        let (packet, source) = receive(&receiver);
+        assert_eq!(packet, b"VBAN");
+        assert_eq!(source.ip(), "::1".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn sends_from_bind_address() {
+        let receiver = receiver_socket("[::1]:0".parse().unwrap(), None, None).unwrap();
+        let target = receiver.local_addr().unwrap();
+        let mut sockets = SenderSockets::new(Some("[::1]:0".parse().unwrap()), 1);
+        sockets.send_to(b"VBAN", target).unwrap();
+        assert_eq!(receive(&receiver).0, b"VBAN");
+        assert!(
+            sockets
+                .send_to(b"VBAN", "127.0.0.1:6980".parse().unwrap())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn dual_stack_receives_ipv4() {
+        let receiver = receiver_socket("[::]:0".parse().unwrap(), None, None).unwrap();
+        let port = receiver.local_addr().unwrap().port();
+        let mut sockets = SenderSockets::new(None, 1);
+        sockets
+            .send_to(b"VBAN", SocketAddr::from((Ipv4Addr::LOCALHOST, port)))
+            .unwrap();
+        sockets
+            .send_to(b"VBAN", SocketAddr::from((Ipv6Addr::LOCALHOST, port)))
+            .unwrap();
+        let (_, first) = receive(&receiver);
+        let (_, second) = receive(&receiver);
+        let mut sources = [first.ip().to_canonical(), second.ip().to_canonical()];
+        sources.sort();
+        assert_eq!(
+            sources,
+            [
+                IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(Ipv6Addr::LOCALHOST)
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_bind_failures() {
+        let taken = receiver_socket("127.0.0.1:0".parse().unwrap(), None, None).unwrap();
+        let address = taken.local_addr().unwrap();
+        assert!(matches!(
+            receiver_socket(address, None, None),
+            Err(VbanError::SocketBind(x, _)) if x == address
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_multicast_settings() {
+        let v4: SocketAddr = "0.0.0.0:0".parse().unwrap();
+        let v6: SocketAddr = "[::]:0".parse().unwrap();
+        let group_v4: IpAddr = "239.255.86.66".parse().unwrap();
+        let group_v6: IpAddr = "ff02::1:3a".parse().unwrap();
+        for (address, group, interface) in [
+            (v4, Some("127.0.0.1".parse().unwrap()), None),
+            (v4, None, Some(Interface::Index(1))),
+            (v4, Some(group_v6), None),
+            (v6, Some(group_v4), None),
+            (v4, Some(group_v4), Some(Interface::Index(1))),
+            (
+                v6,
+                Some(group_v6),
+                Some(Interface::Address(Ipv4Addr::LOCALHOST)),
+            ),
+        ] {
+            assert!(
+                matches!(
+                    receiver_socket(address, group, interface),
+                    Err(VbanError::UnsupportedConfig(_))
+                ),
+                "accepted {:?} on {:?} for {}",
+                group,
+                interface,
+                address
+            );
+        }
+    }
+
+    /// Sends to `group` on the port of a receiver that joined it and
+    /// checks the packet loops back, or `None` if the host cannot join the
+    /// group or send to it
+    fn multicast_loops_back(bind_address: SocketAddr, group: IpAddr) -> Option<()> {
+        let receiver = match receiver_socket(bind_address, Some(group), None) {
+            Ok(x) => x,
+            Err(err) => {
+                eprintln!("Skipping, cannot join {}: {}", group, err);
+                return None;
+            }
+        };
+        let target = SocketAddr::new(group, receiver.local_addr().unwrap().port());
+        let mut sockets = SenderSockets::new(None, 1);
+        if let Err(err) = sockets.send_to(b"VBAN", target) {
+            eprintln!("Skipping, cannot send to {}: {}", group, err);
+            return None;
+        }
+        assert_eq!(receive(&receiver).0, b"VBAN");
+        Some(())
+    }
+
+    #[test]
+    fn receives_ipv4_multicast() {
+        multicast_loops_back(
+            "0.0.0.0:0".parse().unwrap(),
+            "239.255.86.66".parse().unwrap(),
+        );
+    }
+
+    #[test]
+    fn receives_ipv6_multicast() {
+        multicast_loops_back("[::]:0".parse().unwrap(), "ff02::1:3a".parse().unwrap());
+    }
+
+    #[test]
+    fn joins_on_the_given_interface() {
+        let group = "239.255.86.67".parse().unwrap();
+        let any: SocketAddr = "0.0.0.0:0".parse().unwrap();
+        if receiver_socket(any, Some(group), None).is_err() {
+            eprintln!("Skipping, cannot join {}", group);
+            return;
+        }
+        let interface = Interface::Address(Ipv4Addr::LOCALHOST);
+        assert!(receiver_socket(any, Some(group), Some(interface)).is_ok());
+        // an address no interface of this host has, from TEST-NET-3
+        let interface = Interface::Address("203.0.113.254".parse().unwrap());
+        assert!(matches!(
+            receiver_socket(any, Some(group), Some(interface)),
+            Err(VbanError::SocketBind(..))
+        ));
+    }
+
+    #[test]
+    fn sender_sockets_allow_broadcast_and_set_ttl() {
+        let socket = sender_socket("0.0.0.0:0".parse().unwrap(), 3).unwrap();
+        let socket = socket2::SockRef::from(&socket);
+        assert!(socket.broadcast().unwrap());
+        assert_eq!(socket.multicast_ttl_v4().unwrap(), 3);
+        let socket = sender_socket("[::]:0".parse().unwrap(), 5).unwrap();
+        assert_eq!(
+            socket2::SockRef::from(&socket).multicast_hops_v6().unwrap(),
+            5
+        );
+    }
+}
@@ -0,0 +1,393 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use cpal::SampleRate;
+
+use crate::channels::ChannelMap;
+use crate::codec::decode_packet;
+use crate::error::{Result, VbanError};
+use crate::header::VbanHeader;
+use crate::jitter::{JitterConfig, JitterProducer, jitter_buffer};
+use crate::mixer::Mixer;
+use crate::net::{self, Interface};
+use crate::record::{RecordConfig, Recorder};
+use crate::resample::{ResamplerQuality, StreamResampler};
+use crate::sequence::Sequencer;
+use crate::service::{self, Identity};
+use crate::sink::AudioSink;
+use crate::stats::ReceiverStats;
+
+/// Streams that send nothing for this long are removed from the mix
+const STREAM_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone)]
+pub struct ReceiverConfig {
+    /// The address to bind the UDP socket to
+    pub bind_address: SocketAddr,
+    /// The multicast group to join
+    pub multicast_group: Option<IpAddr>,
+    /// The interface to join the multicast group on, any if not set
+    pub interface: Option<Interface>,
+    /// Only play the stream with this name
+    pub stream_name: Option<String>,
+    /// Only play streams sent from this address
+    pub source: Option<IpAddr>,
+    /// How stream channels are routed to the device channels
+    pub channel_map: Option<ChannelMap>,
+    /// Gains applied to streams by name when mixing, 1.0 for any other
+    pub gains: HashMap<String, f32>,
+    pub resampler_quality: ResamplerQuality,
+    pub jitter: JitterConfig,
+    /// Answers VBAN service pings with this identity when set
+    pub identity: Option<Identity>,
+    /// Records every stream at its own rate and channel count when set
+    pub record: Option<RecordConfig>,
+}
+
+impl ReceiverConfig {
+    pub fn new(bind_address: SocketAddr) -> Self {
+        Self {
+            bind_address,
+            multicast_group: None,
+            interface: None,
+            stream_name: None,
+            source: None,
+            channel_map: None,
+            gains: HashMap::new(),
+            resampler_quality: ResamplerQuality::default(),
+            jitter: JitterConfig::new(Duration::from_millis(10)),
+            identity: None,
+            record: None,
+        }
+    }
+}
+
+/// Listens for VBAN packets and plays them on an output, records them,
+/// or both, until stopped or dropped.
+///
+/// Packets are told apart by source address and stream name, and every
+/// stream gets its own jitter buffer before being mixed into the output. The
+/// output is opened at the rate of the first stream if it supports it, and
+/// any stream at another rate is resampled to the output rate.
+/// Packets of each stream are played in frame counter order, see
+/// `Sequencer`. Recordings are taken before channel mapping and resampling.
+pub struct VbanReceiver {
+    local_addr: SocketAddr,
+    stats: Arc<ReceiverStats>,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl VbanReceiver {
+    pub fn start(output: Option<Box<dyn AudioSink>>, config: ReceiverConfig) -> Result<Self> {
+        if output.is_none() && config.record.is_none() {
+            return Err(VbanError::UnsupportedConfig(String::from(
+                "Neither an output nor a recording to receive to",
+            )));
+        }
+        config.jitter.validate()?;
+        let socket = net::receiver_socket(
+            config.bind_address,
+            config.multicast_group,
+            config.interface,
+        )?;
+        // wake up periodically so that stop() and timeouts are noticed
+        let local_addr = socket
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .and_then(|_| socket.local_addr())
+            .map_err(|err| VbanError::SocketBind(config.bind_address, err))?;
+
+        let stats = Arc::new(ReceiverStats::default());
+        let thread_stats = stats.clone();
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = running.clone();
+        let thread = thread::spawn(move || {
+            // cpal streams cannot be moved between threads, so the output
+            // stream lives on the network thread
+            let mut mixer: Option<Mixer> = None;
+            // an output that failed to open is not retried for every packet,
+            // only once the streams it was opened for have ended
+            let mut mixer_failed = false;
+            let mut streams: HashMap<(SocketAddr, String), Incoming> = HashMap::new();
+            let mut next_id = 0;
+            // large enough for any UDP datagram, so nothing is truncated
+            let mut buffer = vec![0u8; 65536];
+            while thread_running.load(Ordering::Relaxed) {
+                let received = socket.recv_from(&mut buffer);
+                if let Ok((amt, source)) = received
+                    && let Some(identity) = &config.identity
+                    && let Some(reply) = service::answer_ping(&buffer[..amt], identity)
+                {
+                    let _ = socket.send_to(&reply, source);
+                } else if let Ok((amt, source)) = received
+                    // IPv4 peers of a dual-stack socket show up IPv4-mapped
+                    && let source = SocketAddr::new(source.ip().to_canonical(), source.port())
+                    && config.source.is_none_or(|x| x == source.ip())
+                    && let Ok((header, samples)) = decode_packet(&buffer[..amt])
+                    && config
+                        .stream_name
+                        .as_ref()
+                        .is_none_or(|x| *x == header.stream_name())
+                    // decode_packet only accepts known sample rates
+                    && let Some(rate) = header.sample_rate()
+                {
+                    if let Some(output) = &output
+                        && mixer.is_none()
+                        && !mixer_failed
+                    {
+                        match Mixer::open(output.as_ref(), rate) {
+                            Ok(x) => mixer = Some(x),
+                            Err(err) => {
+                                eprintln!("Failed to open output stream: {}", err);
+                                mixer_failed = true;
+                            }
+                        }
+                    }
+                    let mixer = mixer.as_ref();
+
+                    let key = (source, header.stream_name());
+                    let channels = header.channels as usize;
+                    if let Some(incoming) = streams.get(&key)
+                        && (incoming.rate != rate || incoming.channels != channels)
+                    {
+                        if let Some(mixer) = mixer {
+                            mixer.remove(incoming.id);
+                        }
+                        streams.remove(&key);
+                    }
+                    let incoming = match streams.get_mut(&key) {
+                        Some(x) => x,
+                        None => {
+                            next_id += 1;
+                            match Incoming::new(
+                                next_id,
+                                &header,
+                                mixer,
+                                &config,
+                                thread_stats.clone(),
+                            ) {
+                                Ok(x) => {
+                                    println!(
+                                        "Receiving stream \"{}\" from {} at {} Hz.",
+                                        key.1, source, rate.0
+                                    );
+                                    streams.entry(key).or_insert(x)
+                                }
+                                Err(err) => {
+                                    eprintln!("Failed to play stream \"{}\": {}", key.1, err);
+                                    continue;
+                                }
+                            }
+                        }
+                    };
+                    incoming.last_seen = Instant::now();
+                    for samples in incoming.sequencer.push(&header, samples) {
+                        incoming.play(&samples);
+                    }
+                }
+
+                streams.retain(|(source, name), incoming| {
+                    let alive = incoming.last_seen.elapsed() < STREAM_TIMEOUT;
+                    if !alive {
+                        println!("Stream \"{}\" from {} ended.", name, source);
+                        if let Some(mixer) = &mixer {
+                            mixer.remove(incoming.id);
+                        }
+                    }
+                    alive
+                });
+                // with nothing left to play the next stream may pick the rate
+                if streams.is_empty() {
+                    mixer = None;
+                    mixer_failed = false;
+                }
+            }
+        });
+
+        Ok(Self {
+            local_addr,
+            stats,
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    /// The address the socket is bound to, telling the port picked when
+    /// binding to port 0
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn stats(&self) -> Arc<ReceiverStats> {
+        self.stats.clone()
+    }
+
+    /// Stops playback and waits for the network thread to finish
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for VbanReceiver {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// A stream being received, with everything needed to bring its packets to
+/// the mixer and the recording
+struct Incoming {
+    id: u64,
+    rate: SampleRate,
+    channels: usize,
+    sequencer: Sequencer,
+    output: Option<Output>,
+    recorder: Option<Recorder>,
+    last_seen: Instant,
+}
+
+/// The path of a stream into the mixer
+struct Output {
+    mix_channels: usize,
+    channel_map: ChannelMap,
+    resampler: Option<StreamResampler>,
+    producer: JitterProducer,
+}
+
+impl Incoming {
+    fn new(
+        id: u64,
+        header: &VbanHeader,
+        mixer: Option<&Mixer>,
+        config: &ReceiverConfig,
+        stats: Arc<ReceiverStats>,
+    ) -> Result<Self> {
+        let rate = header
+            .sample_rate()
+            .ok_or_else(|| VbanError::MalformedPacket(String::from("unknown sample rate")))?;
+        let channels = header.channels as usize;
+        // a stream that cannot be recorded, such as one too wide for FLAC,
+        // still plays
+        let recorder =
+            config
+                .record
+                .as_ref()
+                .and_then(|record| match Recorder::new(record, header) {
+                    Ok(x) => Some(x),
+                    Err(err) => {
+                        eprintln!("Not recording stream \"{}\": {}", header.stream_name(), err);
+                        None
+                    }
+                });
+        // the stream is kept when it cannot be played, such as with a
+        // channel map that does not fit, so that this is only reported once
+        let output =
+            mixer.and_then(
+                |mixer| match Output::new(id, header, mixer, config, stats.clone()) {
+                    Ok(x) => Some(x),
+                    Err(err) => {
+                        eprintln!(
+                            "Failed to play stream \"{}\": {}",
+                            header.stream_name(),
+                            err
+                        );
+                        None
+                    }
+                },
+            );
+        let sequencer = Sequencer::new(config.jitter.target, channels, stats);
+        Ok(Self {
+            id,
+            rate,
+            channels,
+            sequencer,
+            output,
+            recorder,
+            last_seen: Instant::now(),
+        })
+    }
+
+    fn play(&mut self, samples: &[f32]) {
+        if let Some(recorder) = &mut self.recorder
+            && let Err(err) = recorder.write(samples)
+        {
+            eprintln!("Recording failed, no longer recording the stream: {}", err);
+            self.recorder = None;
+        }
+        if let Some(output) = &mut self.output {
+            output.play(samples, self.channels);
+        }
+    }
+}
+
+impl Output {
+    fn new(
+        id: u64,
+        header: &VbanHeader,
+        mixer: &Mixer,
+        config: &ReceiverConfig,
+        stats: Arc<ReceiverStats>,
+    ) -> Result<Self> {
+        let rate = header
+            .sample_rate()
+            .ok_or_else(|| VbanError::MalformedPacket(String::from("unknown sample rate")))?;
+        let channels = header.channels as usize;
+        let mix_channels = mixer.channels();
+        let channel_map = match &config.channel_map {
+            Some(map) => {
+                map.validate(channels, mix_channels)?;
+                map.clone()
+            }
+            None => ChannelMap::default_for(channels, mix_channels),
+        };
+        let resampler = if mixer.rate() != rate {
+            Some(StreamResampler::new(
+                rate,
+                mixer.rate(),
+                mix_channels,
+                config.resampler_quality,
+            )?)
+        } else {
+            None
+        };
+        let (producer, consumer) = jitter_buffer(&config.jitter, mixer.rate(), mix_channels, stats);
+        let gain = config
+            .gains
+            .get(&header.stream_name())
+            .copied()
+            .unwrap_or(1.0);
+        mixer.add(id, consumer, gain);
+        Ok(Self {
+            mix_channels,
+            channel_map,
+            resampler,
+            producer,
+        })
+    }
+
+    fn play(&mut self, samples: &[f32], channels: usize) {
+        let samples = self.channel_map.apply(samples, channels, self.mix_channels);
+        let samples = match &mut self.resampler {
+            Some(resampler) => match resampler.process(&samples) {
+                Ok(x) => x,
+                Err(err) => {
+                    eprintln!("Resampling failed: {}", err);
+                    return;
+                }
+            },
+            None => samples,
+        };
+        self.producer.push(&samples);
+    }
+}
@@ -0,0 +1,460 @@
+// Recording of received streams to WAV, RF64 and FLAC files
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::codec::float_to_int;
+use crate::error::{Result, unsupported};
+use crate::flac::{self, FlacWriter};
+use crate::header::{DataFormat, VbanHeader};
+
+/// How often the WAV header is brought up to date, so that a recording
+/// killed midway stays readable
+const HEADER_UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Size of the JUNK chunk reserved for the ds64 chunk of RF64 files
+const DS64_SIZE: u32 = 28;
+
+#[derive(Debug, Clone)]
+pub struct RecordConfig {
+    /// Where to record to. Every stream gets its own numbered files named
+    /// after this path and the stream name, FLAC if the extension is
+    /// `.flac` and WAV otherwise.
+    pub path: PathBuf,
+    /// Start a new file once a file reaches this many bytes
+    pub max_size: Option<u64>,
+    /// Start a new file once a file holds this much audio
+    pub max_duration: Option<Duration>,
+}
+
+impl RecordConfig {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            max_size: None,
+            max_duration: None,
+        }
+    }
+
+    fn is_flac(&self) -> bool {
+        self.path
+            .extension()
+            .is_some_and(|x| x.eq_ignore_ascii_case("flac"))
+    }
+}
+
+/// The sample representation of a recording
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SampleFormat {
+    Int(u32),
+    Float(u32),
+}
+
+impl SampleFormat {
+    /// The format keeping all of the precision of `format`, limited to the
+    /// integer depths FLAC supports if `flac`
+    fn for_stream(format: DataFormat, flac: bool) -> Self {
+        match format {
+            DataFormat::U8 => SampleFormat::Int(8),
+            DataFormat::I16 | DataFormat::Bits12 | DataFormat::Bits10 => SampleFormat::Int(16),
+            DataFormat::I24 => SampleFormat::Int(24),
+            DataFormat::I32 | DataFormat::F32 | DataFormat::F64 if flac => SampleFormat::Int(24),
+            DataFormat::I32 => SampleFormat::Int(32),
+            DataFormat::F32 => SampleFormat::Float(32),
+            DataFormat::F64 => SampleFormat::Float(64),
+        }
+    }
+
+    fn bits(self) -> u32 {
+        match self {
+            SampleFormat::Int(x) | SampleFormat::Float(x) => x,
+        }
+    }
+}
+
+trait AudioWriter {
+    fn write(&mut self, samples: &[f32]) -> io::Result<()>;
+    /// Bytes written to the file so far
+    fn len(&self) -> u64;
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+/// Writes a WAV file, turning it into RF64 once it outgrows 4 GiB
+struct WavWriter<W: Write + Seek> {
+    writer: W,
+    channels: usize,
+    format: SampleFormat,
+    data_start: u64,
+    data_len: u64,
+    header_updated: Instant,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    fn new(mut writer: W, rate: u32, channels: usize, format: SampleFormat) -> io::Result<Self> {
+        let block_align = channels as u32 * format.bits() / 8;
+        let fmt_len: u32 = match format {
+            SampleFormat::Int(_) => 16,
+            // non-PCM formats carry the size of an empty extension
+            SampleFormat::Float(_) => 18,
+        };
+        let mut header = Vec::new();
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(b"WAVE");
+        header.extend_from_slice(b"JUNK");
+        header.extend_from_slice(&DS64_SIZE.to_le_bytes());
+        header.extend_from_slice(&[0; DS64_SIZE as usize]);
+        header.extend_from_slice(b"fmt ");
+        header.extend_from_slice(&fmt_len.to_le_bytes());
+        let tag: u16 = match format {
+            SampleFormat::Int(_) => 1,
+            SampleFormat::Float(_) => 3,
+        };
+        header.extend_from_slice(&tag.to_le_bytes());
+        header.extend_from_slice(&(channels as u16).to_le_bytes());
+        header.extend_from_slice(&rate.to_le_bytes());
+        header.extend_from_slice(&(rate * block_align).to_le_bytes());
+        header.extend_from_slice(&(block_align as u16).to_le_bytes());
+        header.extend_from_slice(&(format.bits() as u16).to_le_bytes());
+        if fmt_len == 18 {
+            header.extend_from_slice(&0u16.to_le_bytes());
+        }
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&0u32.to_le_bytes());
+        writer.write_all(&header)?;
+        Ok(Self {
+            writer,
+            channels,
+            format,
+            data_start: header.len() as u64,
+            data_len: 0,
+            header_updated: Instant::now(),
+            buffer: Vec::new(),
+        })
+    }
+
+    /// Writes the chunk sizes for the data written so far
+    fn update_header(&mut self) -> io::Result<()> {
+        let pad = self.data_len % 2;
+        let riff_len = self.data_start - 8 + self.data_len + pad;
+        if let (Ok(riff_len), Ok(data_len)) =
+            (u32::try_from(riff_len), u32::try_from(self.data_len))
+        {
+            self.writer.seek(SeekFrom::Start(4))?;
+            self.writer.write_all(&riff_len.to_le_bytes())?;
+            self.writer.seek(SeekFrom::Start(self.data_start - 4))?;
+            self.writer.write_all(&data_len.to_le_bytes())?;
+        } else {
+            let block_align = self.channels as u64 * self.format.bits() as u64 / 8;
+            let mut ds64 = Vec::new();
+            ds64.extend_from_slice(b"ds64");
+            ds64.extend_from_slice(&DS64_SIZE.to_le_bytes());
+            ds64.extend_from_slice(&riff_len.to_le_bytes());
+            ds64.extend_from_slice(&self.data_len.to_le_bytes());
+            ds64.extend_from_slice(&(self.data_len / block_align).to_le_bytes());
+            ds64.extend_from_slice(&0u32.to_le_bytes());
+            self.writer.seek(SeekFrom::Start(0))?;
+            self.writer.write_all(b"RF64")?;
+            self.writer.write_all(&u32::MAX.to_le_bytes())?;
+            self.writer.seek(SeekFrom::Start(12))?;
+            self.writer.write_all(&ds64)?;
+            self.writer.seek(SeekFrom::Start(self.data_start - 4))?;
+            self.writer.write_all(&u32::MAX.to_le_bytes())?;
+        }
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+        self.header_updated = Instant::now();
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> AudioWriter for WavWriter<W> {
+    fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        self.buffer.clear();
+        for &sample in samples {
+            match self.format {
+                SampleFormat::Int(8) => self.buffer.push((float_to_int(sample, 8) + 128) as u8),
+                SampleFormat::Int(bits) => {
+                    let bytes = (float_to_int(sample, bits as usize) as i32).to_le_bytes();
+                    self.buffer.extend_from_slice(&bytes[..bits as usize / 8]);
+                }
+                SampleFormat::Float(32) => self.buffer.extend_from_slice(&sample.to_le_bytes()),
+                SampleFormat::Float(_) => self
+                    .buffer
+                    .extend_from_slice(&(sample as f64).to_le_bytes()),
+            }
+        }
+        self.writer.write_all(&self.buffer)?;
+        self.data_len += self.buffer.len() as u64;
+        if self.header_updated.elapsed() >= HEADER_UPDATE_INTERVAL {
+            self.update_header()?;
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> u64 {
+        self.data_start + self.data_len
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if self.data_len % 2 == 1 {
+            self.writer.write_all(&[0])?;
+        }
+        self.update_header()
+    }
+}
+
+impl<W: Write + Seek> AudioWriter for (FlacWriter<W>, SampleFormat) {
+    fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        let bits = self.1.bits() as usize;
+        let samples: Vec<i32> = samples
+            .iter()
+            .map(|&x| float_to_int(x, bits) as i32)
+            .collect();
+        self.0.write(&samples)
+    }
+
+    fn len(&self) -> u64 {
+        self.0.bytes_written()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.0.finish()
+    }
+}
+
+/// Records one received stream, starting a new file whenever the current one
+/// reaches the size or duration limit
+pub(crate) struct Recorder {
+    config: RecordConfig,
+    stream_name: String,
+    rate: u32,
+    channels: usize,
+    format: SampleFormat,
+    writer: Option<Box<dyn AudioWriter + Send>>,
+    /// Frames in the current file
+    frames: u64,
+}
+
+impl Recorder {
+    pub(crate) fn new(config: &RecordConfig, header: &VbanHeader) -> Result<Self> {
+        let rate = match header.sample_rate() {
+            Some(x) => x.0,
+            None => unsupported!("unknown sample rate"),
+        };
+        let channels = header.channels as usize;
+        let flac = config.is_flac();
+        if flac && channels > flac::MAX_CHANNELS {
+            unsupported!(
+                "FLAC records at most {} channels, use WAV for {}",
+                flac::MAX_CHANNELS,
+                channels
+            );
+        }
+        Ok(Self {
+            config: config.clone(),
+            stream_name: header.stream_name(),
+            rate,
+            channels,
+            format: SampleFormat::for_stream(header.data_format, flac),
+            writer: None,
+            frames: 0,
+        })
+    }
+
+    /// The first free file name for the next part of the recording
+    fn next_path(&self) -> PathBuf {
+        let path = &self.config.path;
+        let directory = path.parent().unwrap_or(Path::new(""));
+        let stem = path
+            .file_stem()
+            .map(|x| x.to_string_lossy().into_owned())
+            .unwrap_or_else(|| String::from("recording"));
+        let extension = if self.config.is_flac() { "flac" } else { "wav" };
+        // stream names may hold anything, keep what is safe in a file name
+        let stream_name: String = self
+            .stream_name
+            .chars()
+            .map(|x| {
+                if x.is_alphanumeric() || x == '-' {
+                    x
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        (1..)
+            .map(|n| directory.join(format!("{stem}-{stream_name}-{n:03}.{extension}")))
+            .find(|x| !x.exists())
+            .unwrap_or_default()
+    }
+
+    fn open(&mut self) -> io::Result<()> {
+        let path = self.next_path();
+        let file = BufWriter::new(File::create(&path)?);
+        let writer: Box<dyn AudioWriter + Send> = match self.format {
+            SampleFormat::Int(bits) if self.config.is_flac() => Box::new((
+                FlacWriter::new(file, self.rate, self.channels, bits)?,
+                self.format,
+            )),
+            _ => Box::new(WavWriter::new(file, self.rate, self.channels, self.format)?),
+        };
+        println!(
+            "Recording stream \"{}\" to {}.",
+            self.stream_name,
+            path.display()
+        );
+        self.writer = Some(writer);
+        self.frames = 0;
+        Ok(())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        match self.writer.take() {
+            Some(mut writer) => writer.finish(),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        let max_frames = self
+            .config
+            .max_duration
+            .map(|x| ((x.as_secs_f64() * self.rate as f64).ceil() as u64).max(1));
+        let mut samples = samples;
+        while !samples.is_empty() {
+            if self.writer.is_none() {
+                self.open()?;
+            }
+            let Some(writer) = &mut self.writer else {
+                break;
+            };
+            // split at the exact frame the duration is reached
+            let len = match max_frames {
+                Some(max) => {
+                    let room = max.saturating_sub(self.frames) as usize * self.channels;
+                    samples.len().min(room)
+                }
+                None => samples.len(),
+            };
+            writer.write(&samples[..len])?;
+            self.frames += (len / self.channels) as u64;
+            samples = &samples[len..];
+            let full = max_frames.is_some_and(|x| self.frames >= x)
+                || self.config.max_size.is_some_and(|x| writer.len() >= x);
+            if full {
+                self.close()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        if let Err(err) = self.close() {
+            eprintln!("Failed to finish recording: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::source::FileDecoder;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vban-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn header(data_format: DataFormat, sample_rate_index: u8, channels: u16) -> VbanHeader {
+        let mut header = VbanHeader {
+            sample_rate_index,
+            channels,
+            data_format,
+            ..Default::default()
+        };
+        header.set_stream_name("Stream1");
+        header
+    }
+
+    fn decode(path: &Path) -> (u32, usize, Vec<f32>) {
+        let mut decoder = FileDecoder::open(path).unwrap();
+        let mut samples = Vec::new();
+        while let Some(x) = decoder.next().unwrap() {
+            samples.extend(x);
+        }
+        (decoder.rate.0, decoder.channels, samples)
+    }
+
+    /// A sine, then silence, then noise, on exact steps of `bits` bits
+    fn signal(frames: usize, channels: usize, bits: u32) -> Vec<f32> {
+        let scale = (1u32 << (bits - 1)) as f32;
+        let mut seed = 1u32;
+        (0..frames * channels)
+            .map(|i| {
+                let frame = i / channels;
+                let x = if frame < frames / 3 {
+                    (frame as f32 * 0.01 * (i % channels + 1) as f32).sin() * 0.8
+                } else if frame < 2 * frames / 3 {
+                    0.0
+                } else {
+                    seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+                    (seed >> 8) as f32 / (1u32 << 24) as f32 - 0.5
+                };
+                (x * scale).round() / scale
+            })
+            .collect()
+    }
+
+    #[test]
+    fn flac_round_trips() {
+        let dir = temp_dir("flac");
+        for (format, bits) in [(DataFormat::I16, 16), (DataFormat::I24, 24)] {
+            let samples = signal(3 * flac::BLOCK_SIZE + 100, 3, bits);
+            let config = RecordConfig::new(dir.join(format!("{bits}.flac")));
+            let mut recorder = Recorder::new(&config, &header(format, 16, 3)).unwrap();
+            for chunk in samples.chunks(3 * 256) {
+                recorder.write(chunk).unwrap();
+            }
+            drop(recorder);
+
+            let (rate, channels, decoded) = decode(&dir.join(format!("{bits}-Stream1-001.flac")));
+            assert_eq!((rate, channels), (44100, 3));
+            assert_eq!(decoded, samples);
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn wav_rotates_by_duration() {
+        let dir = temp_dir("wav");
+        let samples = signal(12000, 2, 24);
+        let config = RecordConfig {
+            max_duration: Some(Duration::from_millis(100)),
+            ..RecordConfig::new(dir.join("take.wav"))
+        };
+        let mut recorder = Recorder::new(&config, &header(DataFormat::F32, 3, 2)).unwrap();
+        for chunk in samples.chunks(2 * 128) {
+            recorder.write(chunk).unwrap();
+        }
+        drop(recorder);
+
+        let mut decoded = Vec::new();
+        for (n, frames) in [(1, 4800), (2, 4800), (3, 2400)] {
+            let (rate, channels, part) = decode(&dir.join(format!("take-Stream1-{n:03}.wav")));
+            assert_eq!((rate, channels, part.len()), (48000, 2, frames * 2));
+            decoded.extend(part);
+        }
+        assert!(!dir.join("take-Stream1-004.wav").exists());
+        assert_eq!(decoded, samples);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,153 @@
+// Sample rate conversion between the network stream and the local device,
+// see https://github.com/HEnquist/rubato
+use cpal::SampleRate;
+use rubato::{
+    FastFixedIn, PolynomialDegree, SincFixedIn, SincInterpolationParameters, SincInterpolationType,
+    VecResampler, WindowFunction,
+};
+
+use crate::error::{Result, VbanError};
+
+/// Trade-off between conversion quality and the latency/CPU it costs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ResamplerQuality {
+    /// Polynomial interpolation without anti-aliasing, lowest latency
+    Fast,
+    /// Short sinc filter
+    #[default]
+    Balanced,
+    /// Long sinc filter, highest latency
+    High,
+}
+
+impl ResamplerQuality {
+    /// Number of input frames processed at once
+    fn chunk_size(self) -> usize {
+        match self {
+            ResamplerQuality::Fast => 64,
+            ResamplerQuality::Balanced => 256,
+            ResamplerQuality::High => 1024,
+        }
+    }
+
+    fn build(self, ratio: f64, channels: usize) -> Result<Box<dyn VecResampler<f32>>> {
+        let chunk_size = self.chunk_size();
+        let unsupported = |err: rubato::ResamplerConstructionError| {
+            VbanError::UnsupportedConfig(format!("Cannot resample: {}", err))
+        };
+        Ok(match self {
+            ResamplerQuality::Fast => Box::new(
+                FastFixedIn::new(ratio, 1.0, PolynomialDegree::Cubic, chunk_size, channels)
+                    .map_err(unsupported)?,
+            ),
+            ResamplerQuality::Balanced => Box::new(
+                SincFixedIn::new(
+                    ratio,
+                    1.0,
+                    SincInterpolationParameters {
+                        sinc_len: 64,
+                        f_cutoff: 0.91,
+                        oversampling_factor: 128,
+                        interpolation: SincInterpolationType::Linear,
+                        window: WindowFunction::BlackmanHarris2,
+                    },
+                    chunk_size,
+                    channels,
+                )
+                .map_err(unsupported)?,
+            ),
+            ResamplerQuality::High => Box::new(
+                SincFixedIn::new(
+                    ratio,
+                    1.0,
+                    SincInterpolationParameters {
+                        sinc_len: 256,
+                        f_cutoff: 0.95,
+                        oversampling_factor: 256,
+                        interpolation: SincInterpolationType::Cubic,
+                        window: WindowFunction::BlackmanHarris2,
+                    },
+                    chunk_size,
+                    channels,
+                )
+                .map_err(unsupported)?,
+            ),
+        })
+    }
+}
+
+/// Converts interleaved audio from one sample rate to another, buffering input
+/// until a full chunk is available
+pub struct StreamResampler {
+    resampler: Box<dyn VecResampler<f32>>,
+    channels: usize,
+    pending: Vec<Vec<f32>>,
+}
+
+impl StreamResampler {
+    pub fn new(
+        from: SampleRate,
+        to: SampleRate,
+        channels: usize,
+        quality: ResamplerQuality,
+    ) -> Result<Self> {
+        let ratio = to.0 as f64 / from.0 as f64;
+        Ok(Self {
+            resampler: quality.build(ratio, channels)?,
+            channels,
+            pending: vec![Vec::new(); channels],
+        })
+    }
+
+    /// Feeds interleaved samples and returns whatever interleaved output is
+    /// ready, which may be empty
+    pub fn process(&mut self, samples: &[f32]) -> Result<Vec<f32>> {
+        for frame in samples.chunks_exact(self.channels) {
+            for (channel, &sample) in self.pending.iter_mut().zip(frame) {
+                channel.push(sample);
+            }
+        }
+
+        let mut output = Vec::new();
+        loop {
+            let needed = self.resampler.input_frames_next();
+            if self.pending[0].len() < needed {
+                break;
+            }
+            let chunk: Vec<Vec<f32>> = self
+                .pending
+                .iter_mut()
+                .map(|channel| channel.drain(..needed).collect())
+                .collect();
+            let resampled = self
+                .resampler
+                .process(&chunk, None)
+                .map_err(|err| VbanError::Stream(err.to_string()))?;
+            interleave(&resampled, &mut output);
+        }
+        Ok(output)
+    }
+
+    /// Converts the input still buffered for want of a full chunk, padded
+    /// with silence, at the end of a stream
+    pub fn flush(&mut self) -> Result<Vec<f32>> {
+        let mut output = Vec::new();
+        if self.pending[0].is_empty() {
+            return Ok(output);
+        }
+        let chunk = std::mem::replace(&mut self.pending, vec![Vec::new(); self.channels]);
+        let resampled = self
+            .resampler
+            .process_partial(Some(&chunk), None)
+            .map_err(|err| VbanError::Stream(err.to_string()))?;
+        interleave(&resampled, &mut output);
+        Ok(output)
+    }
+}
+
+/// Appends the frames of separate channels to `output`, interleaved
+fn interleave(channels: &[Vec<f32>], output: &mut Vec<f32>) {
+    for i in 0..channels[0].len() {
+        output.extend(channels.iter().map(|channel| channel[i]));
+    }
+}
@@ -0,0 +1,239 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, mpsc};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use cpal::SampleRate;
+
+use crate::channels::ChannelMap;
+use crate::codec::Encoder;
+use crate::error::{Result, VbanError, unsupported};
+use crate::header::{self, DataFormat, VBAN_MAX_CHANNELS, VBAN_STREAM_NAME_SIZE, VbanHeader};
+use crate::net::SenderSockets;
+use crate::resample::{ResamplerQuality, StreamResampler};
+use crate::source::AudioSource;
+use crate::target::{Resolver, Target};
+
+#[derive(Debug, Clone)]
+pub struct SenderConfig {
+    /// The targets to send audio data to
+    pub targets: Vec<Target>,
+    /// The VBAN stream name, at most 16 bytes
+    pub stream_name: String,
+    /// The sample rate to send at, the device rate if not set
+    pub stream_rate: Option<SampleRate>,
+    /// The sample format used on the wire
+    pub data_format: DataFormat,
+    /// The number of channels to send, the device channel count if not set
+    pub channels: Option<u16>,
+    /// How device channels are routed to the sent channels
+    pub channel_map: Option<ChannelMap>,
+    /// Samples per channel in every packet, as many as fit if not set
+    pub samples_per_packet: Option<usize>,
+    /// The address to send from, an ephemeral port of the target's address
+    /// family if not set
+    pub bind_address: Option<SocketAddr>,
+    /// How many routers multicast packets may cross
+    pub multicast_ttl: u32,
+    pub resampler_quality: ResamplerQuality,
+}
+
+impl SenderConfig {
+    pub fn new(targets: Vec<Target>) -> Self {
+        Self {
+            targets,
+            stream_name: String::from("Stream1"),
+            stream_rate: None,
+            data_format: DataFormat::F32,
+            channels: None,
+            channel_map: None,
+            samples_per_packet: None,
+            bind_address: None,
+            multicast_ttl: 1,
+            resampler_quality: ResamplerQuality::default(),
+        }
+    }
+}
+
+/// Streams the audio of a source, such as an input device or a file, as
+/// VBAN packets to every target until stopped, dropped or the source ends.
+///
+/// Targets sharing a stream name and format get the same packets, each other
+/// combination is encoded separately with its own frame counter.
+pub struct VbanSender {
+    source: Box<dyn AudioSource>,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl VbanSender {
+    pub fn start(mut source: impl AudioSource + 'static, config: SenderConfig) -> Result<Self> {
+        if config.targets.is_empty() {
+            unsupported!("No target to send to");
+        }
+        for target in &config.targets {
+            let stream_name = target.stream_name.as_ref().unwrap_or(&config.stream_name);
+            if stream_name.len() > VBAN_STREAM_NAME_SIZE {
+                unsupported!(
+                    "Stream name '{}' is longer than {} bytes",
+                    stream_name,
+                    VBAN_STREAM_NAME_SIZE
+                );
+            }
+        }
+        let source_rate = source.sample_rate();
+        let stream_rate = config.stream_rate.unwrap_or(source_rate);
+        let sample_rate_index = header::sample_rate_index(stream_rate).ok_or_else(|| {
+            VbanError::UnsupportedConfig(format!(
+                "Sample rate {} is not supported by VBAN",
+                stream_rate.0
+            ))
+        })?;
+
+        let source_channels = source.channels();
+        let net_channels = config
+            .channels
+            .unwrap_or(u16::try_from(source_channels).unwrap_or(u16::MAX));
+        if net_channels == 0 || net_channels as usize > VBAN_MAX_CHANNELS {
+            unsupported!("VBAN streams carry 1 to {} channels", VBAN_MAX_CHANNELS);
+        }
+        let channel_map = match config.channel_map {
+            Some(map) => {
+                map.validate(source_channels, net_channels as usize)?;
+                map
+            }
+            None => ChannelMap::default_for(source_channels, net_channels as usize),
+        };
+
+        let resolver = Resolver::start(config.targets.clone(), config.bind_address)?;
+        let mut sockets = SenderSockets::new(config.bind_address, config.multicast_ttl);
+        for address in resolver.addresses() {
+            sockets.socket_for(address)?;
+        }
+
+        let mut resampler = if stream_rate != source_rate {
+            Some(StreamResampler::new(
+                source_rate,
+                stream_rate,
+                net_channels as usize,
+                config.resampler_quality,
+            )?)
+        } else {
+            None
+        };
+        // encoders with the indices of the targets they send to
+        let mut outputs: Vec<(Encoder, Vec<usize>)> = Vec::new();
+        for (i, target) in config.targets.iter().enumerate() {
+            let mut header = VbanHeader {
+                sample_rate_index,
+                channels: net_channels,
+                data_format: target.data_format.unwrap_or(config.data_format),
+                ..Default::default()
+            };
+            header.set_stream_name(target.stream_name.as_ref().unwrap_or(&config.stream_name));
+            match outputs.iter_mut().find(|(x, _)| x.header() == &header) {
+                Some((_, indices)) => indices.push(i),
+                None => outputs.push((Encoder::new(header, config.samples_per_packet)?, vec![i])),
+            }
+        }
+        let names: Vec<String> = config.targets.iter().map(|x| x.address.clone()).collect();
+        // whether sending to each target failed last time, so that a lasting
+        // failure is reported once rather than for every packet
+        let mut failing = vec![false; names.len()];
+        let (tx, rx) = mpsc::channel::<Vec<f32>>();
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = running.clone();
+        let thread = thread::spawn(move || {
+            while thread_running.load(Ordering::Relaxed) {
+                let (mut buffer, ended) = match rx.recv_timeout(Duration::from_millis(100)) {
+                    Ok(x) => (x, false),
+                    Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                    // the source ended, what is still held back goes out last
+                    Err(mpsc::RecvTimeoutError::Disconnected) => (Vec::new(), true),
+                };
+                if let Some(resampler) = &mut resampler {
+                    let resampled = if ended {
+                        resampler.flush()
+                    } else {
+                        resampler.process(&buffer)
+                    };
+                    buffer = match resampled {
+                        Ok(x) => x,
+                        Err(err) => {
+                            eprintln!("Resampling failed: {}", err);
+                            Vec::new()
+                        }
+                    };
+                }
+                let addresses = resolver.addresses();
+                for (encoder, indices) in &mut outputs {
+                    let mut packets = encoder.encode(&buffer);
+                    if ended {
+                        packets.extend(encoder.flush());
+                    }
+                    for packet in packets {
+                        for &i in indices.iter() {
+                            match sockets.send_to(&packet, addresses[i]) {
+                                Err(err) if !failing[i] => {
+                                    eprintln!(
+                                        "Failed to send to {} ({}): {}",
+                                        names[i], addresses[i], err
+                                    );
+                                    failing[i] = true;
+                                }
+                                Ok(()) if failing[i] => {
+                                    eprintln!("Sending to {} ({}) again", names[i], addresses[i]);
+                                    failing[i] = false;
+                                }
+                                _ => {}
+                            }
+                        }
+                    }
+                }
+                if ended {
+                    break;
+                }
+            }
+        });
+
+        let started = source.start(Box::new(move |data: &[f32]| {
+            let _ = tx.send(channel_map.apply(data, source_channels, net_channels as usize));
+        }));
+        if let Err(err) = started {
+            running.store(false, Ordering::Relaxed);
+            let _ = thread.join();
+            return Err(err);
+        }
+
+        Ok(Self {
+            source: Box::new(source),
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    /// Whether the source ended and all of its audio was sent
+    pub fn is_finished(&self) -> bool {
+        self.thread.as_ref().is_none_or(|x| x.is_finished())
+    }
+
+    /// Stops capturing and waits for the network thread to finish
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.source.stop();
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for VbanSender {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
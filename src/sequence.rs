@@ -0,0 +1,283 @@
+// Ordering of received packets by their frame counter
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::header::VbanHeader;
+use crate::stats::ReceiverStats;
+
+/// Packets further than this from the expected frame counter are taken as
+/// the sender having restarted rather than as loss or reordering
+const RESYNC_DISTANCE: i32 = 1024;
+
+/// Packets in a row from before the expected frame counter after which the
+/// sender is taken as having restarted with a lower counter, which is closer
+/// than `RESYNC_DISTANCE` if it had sent little before
+const RESYNC_BEHIND: u32 = 4;
+
+/// Number of already played frame counters remembered to tell duplicates
+/// from packets arriving too late
+const HISTORY_LEN: i32 = 64;
+
+/// Puts packets back into frame counter order, holding early packets for up
+/// to `window` of audio while waiting for missing ones, dropping duplicates
+/// and concealing packets that never arrive
+pub struct Sequencer {
+    window: Duration,
+    channels: usize,
+    /// The position of the next packet to play, counted like the frame
+    /// counter but without wrapping around
+    next: Option<u64>,
+    /// Bit `i` is set if packet `next - 1 - i` was played
+    history: u64,
+    pending: BTreeMap<u64, Vec<f32>>,
+    /// Packets in a row that came before `next`
+    behind: u32,
+    /// The last packet played, repeated with a fade out to conceal losses
+    last: Vec<f32>,
+    stats: Arc<ReceiverStats>,
+}
+
+impl Sequencer {
+    pub fn new(window: Duration, channels: usize, stats: Arc<ReceiverStats>) -> Self {
+        Self {
+            window,
+            channels,
+            next: None,
+            history: 0,
+            pending: BTreeMap::new(),
+            behind: 0,
+            last: Vec::new(),
+            stats,
+        }
+    }
+
+    /// Takes a decoded packet and returns the audio that is ready to play,
+    /// in order
+    pub fn push(&mut self, header: &VbanHeader, samples: Vec<f32>) -> Vec<Vec<f32>> {
+        let counter = header.frame_counter;
+        let next = *self.next.get_or_insert(counter as u64);
+        let distance = counter.wrapping_sub(next as u32) as i32;
+
+        let position = if !(-RESYNC_DISTANCE..RESYNC_DISTANCE).contains(&distance)
+            || (distance < 0 && self.behind + 1 >= RESYNC_BEHIND)
+        {
+            self.resync(counter)
+        } else if distance < 0 {
+            self.behind += 1;
+            let age = -distance - 1;
+            if age < HISTORY_LEN && self.history & (1 << age) != 0 {
+                ReceiverStats::count(&self.stats.duplicates);
+            } else {
+                ReceiverStats::count(&self.stats.late);
+            }
+            return Vec::new();
+        } else {
+            self.behind = 0;
+            let position = next + distance as u64;
+            if self.pending.contains_key(&position) {
+                ReceiverStats::count(&self.stats.duplicates);
+                return Vec::new();
+            }
+            if distance == 0 && !self.pending.is_empty() {
+                // later packets overtook this one
+                ReceiverStats::count(&self.stats.reordered);
+            }
+            position
+        };
+        self.pending.insert(position, samples);
+
+        let mut ready = Vec::new();
+        self.drain(&mut ready);
+
+        // give up on missing packets once the waiting audio fills the window
+        let rate = header.sample_rate().map_or(48000, |x| x.0) as f64;
+        let window_packets =
+            (self.window.as_secs_f64() * rate / header.samples_per_frame as f64).ceil() as usize;
+        let window_packets = window_packets.max(1);
+        while self.pending.len() > window_packets {
+            if let (Some(next), Some(&first)) = (self.next, self.pending.keys().next()) {
+                // only conceal up to a window's worth of a long gap
+                let gap = (first - next) as usize;
+                if gap > window_packets {
+                    self.skip(gap - window_packets);
+                }
+            }
+            self.conceal(&mut ready);
+            self.drain(&mut ready);
+        }
+        ready
+    }
+
+    /// Starts over at `counter`, forgetting the packets waiting
+    fn resync(&mut self, counter: u32) -> u64 {
+        self.pending.clear();
+        self.history = 0;
+        self.behind = 0;
+        self.next = Some(counter as u64);
+        counter as u64
+    }
+
+    /// Moves packets that are next in line out of `pending`
+    fn drain(&mut self, ready: &mut Vec<Vec<f32>>) {
+        while let Some(next) = self.next
+            && let Some(samples) = self.pending.remove(&next)
+        {
+            self.advance(true);
+            self.last.clone_from(&samples);
+            ready.push(samples);
+        }
+    }
+
+    /// Plays a faded repetition of the last packet in place of the missing
+    /// next one
+    fn conceal(&mut self, ready: &mut Vec<Vec<f32>>) {
+        ReceiverStats::count(&self.stats.lost);
+        self.advance(false);
+        if self.last.is_empty() {
+            // nothing played yet, fill the gap with silence instead
+            let len = self.pending.values().next().map_or(0, Vec::len);
+            self.last = vec![0.0; len];
+        }
+        let frames = (self.last.len() / self.channels).max(1);
+        let concealed = self
+            .last
+            .iter()
+            .enumerate()
+            .map(|(i, s)| s * (1.0 - (i / self.channels) as f32 / frames as f32))
+            .collect();
+        // a second loss in a row continues from silence
+        self.last.fill(0.0);
+        ready.push(concealed);
+    }
+
+    /// Gives up on `count` missing packets without concealing them
+    fn skip(&mut self, count: usize) {
+        ReceiverStats::add(&self.stats.lost, count as u64);
+        self.next = self.next.map(|x| x + count as u64);
+        self.history = self.history.checked_shl(count as u32).unwrap_or(0);
+    }
+
+    fn advance(&mut self, played: bool) {
+        self.next = self.next.map(|x| x + 1);
+        self.history = (self.history << 1) | played as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use super::*;
+
+    /// Packets of 256 frames at 48 kHz, a 20ms window holding 4 of them
+    fn sequencer() -> (Sequencer, Arc<ReceiverStats>) {
+        let stats = Arc::new(ReceiverStats::default());
+        let sequencer = Sequencer::new(Duration::from_millis(20), 1, stats.clone());
+        (sequencer, stats)
+    }
+
+    /// Pushes a packet whose samples all hold its counter, returning the
+    /// first sample of every packet ready to play
+    fn push(sequencer: &mut Sequencer, counter: u32) -> Vec<f32> {
+        let header = VbanHeader {
+            channels: 1,
+            samples_per_frame: 256,
+            frame_counter: counter,
+            ..Default::default()
+        };
+        sequencer
+            .push(&header, vec![counter as f32; 256])
+            .iter()
+            .map(|x| x[0])
+            .collect()
+    }
+
+    fn counts(stats: &ReceiverStats) -> [u64; 4] {
+        [
+            stats.lost.load(Ordering::Relaxed),
+            stats.duplicates.load(Ordering::Relaxed),
+            stats.reordered.load(Ordering::Relaxed),
+            stats.late.load(Ordering::Relaxed),
+        ]
+    }
+
+    #[test]
+    fn plays_in_order_packets_at_once() {
+        let (mut sequencer, stats) = sequencer();
+        for counter in 10..20 {
+            assert_eq!(push(&mut sequencer, counter), [counter as f32]);
+        }
+        assert_eq!(counts(&stats), [0; 4]);
+    }
+
+    #[test]
+    fn reorders_overtaken_packets() {
+        let (mut sequencer, stats) = sequencer();
+        assert_eq!(push(&mut sequencer, 0), [0.0]);
+        assert!(push(&mut sequencer, 2).is_empty());
+        assert!(push(&mut sequencer, 3).is_empty());
+        assert_eq!(push(&mut sequencer, 1), [1.0, 2.0, 3.0]);
+        assert_eq!(counts(&stats), [0, 0, 1, 0]);
+    }
+
+    #[test]
+    fn drops_duplicates() {
+        let (mut sequencer, stats) = sequencer();
+        push(&mut sequencer, 0);
+        push(&mut sequencer, 1);
+        // one already played, one still waiting
+        assert!(push(&mut sequencer, 1).is_empty());
+        assert!(push(&mut sequencer, 3).is_empty());
+        assert!(push(&mut sequencer, 3).is_empty());
+        assert_eq!(counts(&stats), [0, 2, 0, 0]);
+    }
+
+    #[test]
+    fn conceals_lost_packets_once_the_window_is_full() {
+        let (mut sequencer, stats) = sequencer();
+        push(&mut sequencer, 10);
+        for counter in 12..16 {
+            assert!(push(&mut sequencer, counter).is_empty());
+        }
+        // the fifth waiting packet overflows the window
+        let ready = push(&mut sequencer, 16);
+        // the repetition of packet 10 stands in for packet 11
+        assert_eq!(ready, [10.0, 12.0, 13.0, 14.0, 15.0, 16.0]);
+        assert_eq!(counts(&stats), [1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn counts_packets_arriving_after_concealment_as_late() {
+        let (mut sequencer, stats) = sequencer();
+        push(&mut sequencer, 0);
+        for counter in 2..7 {
+            push(&mut sequencer, counter);
+        }
+        assert!(push(&mut sequencer, 1).is_empty());
+        assert_eq!(push(&mut sequencer, 7), [7.0]);
+        assert_eq!(counts(&stats), [1, 0, 0, 1]);
+    }
+
+    #[test]
+    fn follows_a_sender_restarting_far_away() {
+        let (mut sequencer, stats) = sequencer();
+        push(&mut sequencer, 100_000);
+        assert_eq!(push(&mut sequencer, 3), [3.0]);
+        assert_eq!(push(&mut sequencer, 4), [4.0]);
+        assert_eq!(counts(&stats), [0; 4]);
+    }
+
+    #[test]
+    fn follows_a_sender_restarting_slightly_lower() {
+        let (mut sequencer, stats) = sequencer();
+        push(&mut sequencer, 500);
+        push(&mut sequencer, 501);
+        for counter in 100..103 {
+            assert!(push(&mut sequencer, counter).is_empty());
+        }
+        assert_eq!(push(&mut sequencer, 103), [103.0]);
+        assert_eq!(push(&mut sequencer, 104), [104.0]);
+        assert_eq!(counts(&stats), [0, 0, 0, 3]);
+    }
+}
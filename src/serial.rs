@@ -0,0 +1,232 @@
+// VBAN-SERIAL, carrying the bytes of a serial line or MIDI port
+use std::net::{SocketAddr, UdpSocket};
+
+use crate::error::{Result, VbanError, malformed, unsupported};
+use crate::header::{
+    SubProtocol, VBAN_HEADER_SIZE, VBAN_MAX_PAYLOAD_SIZE, VBAN_STREAM_NAME_SIZE, VbanHeader,
+    bit_rate_index,
+};
+use crate::net::{self, SenderSockets};
+use crate::target::Target;
+
+const STREAM_TYPE_MASK: u8 = 0xF0;
+
+/// What the bytes of a serial stream carry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum SerialFormat {
+    #[default]
+    Generic,
+    Midi,
+}
+
+impl SerialFormat {
+    fn from_bits(bits: u8) -> Option<Self> {
+        match bits & STREAM_TYPE_MASK {
+            0x00 => Some(SerialFormat::Generic),
+            0x10 => Some(SerialFormat::Midi),
+            _ => None,
+        }
+    }
+
+    fn bits(self) -> u8 {
+        match self {
+            SerialFormat::Generic => 0x00,
+            SerialFormat::Midi => 0x10,
+        }
+    }
+}
+
+/// A VBAN-SERIAL packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SerialPacket {
+    pub stream_name: String,
+    /// Index into `VBAN_BIT_RATES`
+    pub bit_rate_index: u8,
+    /// The serial line settings (stop bits, start bit, parity) as sent in
+    /// byte 5 of the header, kept as is
+    pub mode: u8,
+    /// Identifies the channel within the stream
+    pub channel: u8,
+    pub format: SerialFormat,
+    pub frame_counter: u32,
+    pub data: Vec<u8>,
+}
+
+impl SerialPacket {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut header = VbanHeader {
+            sub_protocol: SubProtocol::Serial,
+            sample_rate_index: self.bit_rate_index,
+            frame_counter: self.frame_counter,
+            ..Default::default()
+        };
+        header.set_stream_name(&self.stream_name);
+        let mut bytes = header.to_bytes().to_vec();
+        // the audio fields of bytes 5 to 7 mean something else for serial
+        // data, the data type in the low bits of byte 7 being bytes
+        bytes[5] = self.mode;
+        bytes[6] = self.channel;
+        bytes[7] = self.format.bits();
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+
+    pub fn from_bytes(packet: &[u8]) -> Result<Self> {
+        let header = VbanHeader::from_bytes(packet)?;
+        if header.sub_protocol != SubProtocol::Serial {
+            malformed!("not a serial packet: {:?}", header.sub_protocol);
+        }
+        let format = SerialFormat::from_bits(packet[7]).ok_or_else(|| {
+            VbanError::MalformedPacket(format!("unknown serial stream type {:#04x}", packet[7]))
+        })?;
+        Ok(Self {
+            stream_name: header.stream_name(),
+            bit_rate_index: header.sample_rate_index,
+            mode: packet[5],
+            channel: packet[6],
+            format,
+            frame_counter: header.frame_counter,
+            data: packet[VBAN_HEADER_SIZE..].to_vec(),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SerialSenderConfig {
+    /// Where to send the data
+    pub target: Target,
+    pub stream_name: String,
+    /// The bit rate of the serial line, 0 if unspecified
+    pub bit_rate: u32,
+    pub channel: u8,
+    pub format: SerialFormat,
+    /// The address to send from, an ephemeral port of the target's address
+    /// family if not set
+    pub bind_address: Option<SocketAddr>,
+}
+
+impl SerialSenderConfig {
+    pub fn new(target: Target) -> Self {
+        Self {
+            target,
+            stream_name: String::from("Serial1"),
+            bit_rate: 0,
+            channel: 0,
+            format: SerialFormat::default(),
+            bind_address: None,
+        }
+    }
+}
+
+/// Sends bytes as VBAN-SERIAL packets, splitting data too long for one
+/// packet
+pub struct SerialSender {
+    packet: SerialPacket,
+    target: SocketAddr,
+    sockets: SenderSockets,
+}
+
+impl SerialSender {
+    pub fn new(config: SerialSenderConfig) -> Result<Self> {
+        if config.stream_name.len() > VBAN_STREAM_NAME_SIZE {
+            unsupported!(
+                "Stream name '{}' is longer than {} bytes",
+                config.stream_name,
+                VBAN_STREAM_NAME_SIZE
+            );
+        }
+        // serial lines may run at rates VBAN has no index for
+        let bit_rate_index = bit_rate_index(config.bit_rate).unwrap_or(0);
+        let target = config.target.resolve(config.bind_address)?;
+        let mut sockets = SenderSockets::new(config.bind_address, 1);
+        sockets.socket_for(target)?;
+        Ok(Self {
+            packet: SerialPacket {
+                stream_name: config.stream_name,
+                bit_rate_index,
+                mode: 0,
+                channel: config.channel,
+                format: config.format,
+                frame_counter: 0,
+                data: Vec::new(),
+            },
+            target,
+            sockets,
+        })
+    }
+
+    pub fn send(&mut self, data: &[u8]) -> Result<()> {
+        for chunk in data.chunks(VBAN_MAX_PAYLOAD_SIZE) {
+            self.packet.data.clear();
+            self.packet.data.extend_from_slice(chunk);
+            self.sockets.send_to(&self.packet.to_bytes(), self.target)?;
+            self.packet.frame_counter = self.packet.frame_counter.wrapping_add(1);
+        }
+        Ok(())
+    }
+}
+
+/// Receives VBAN-SERIAL packets, ignoring packets of other sub-protocols
+pub struct SerialListener {
+    socket: UdpSocket,
+    buffer: Vec<u8>,
+}
+
+impl SerialListener {
+    pub fn bind(bind_address: SocketAddr) -> Result<Self> {
+        Ok(Self {
+            socket: net::receiver_socket(bind_address, None, None)?,
+            buffer: vec![0u8; 65536],
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// Waits for the next serial packet and the address it came from
+    pub fn recv(&mut self) -> Result<(SocketAddr, SerialPacket)> {
+        loop {
+            let (amt, source) = self.socket.recv_from(&mut self.buffer)?;
+            if let Ok(packet) = SerialPacket::from_bytes(&self.buffer[..amt]) {
+                let source = SocketAddr::new(source.ip().to_canonical(), source.port());
+                return Ok((source, packet));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_header_fields() {
+        let packet = SerialPacket {
+            stream_name: String::from("MIDI1"),
+            bit_rate_index: 11,
+            mode: 0x04,
+            channel: 3,
+            format: SerialFormat::Midi,
+            frame_counter: 42,
+            data: vec![0x90, 0x3C, 0x7F],
+        };
+        let bytes = packet.to_bytes();
+        assert_eq!(bytes[4], 0x20 | 11);
+        assert_eq!(bytes[7], 0x10);
+        assert_eq!(SerialPacket::from_bytes(&bytes).unwrap(), packet);
+    }
+
+    #[test]
+    fn sends_to_listener_in_chunks() {
+        let mut listener = SerialListener::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let target = Target::new(listener.local_addr().unwrap().to_string());
+        let mut sender = SerialSender::new(SerialSenderConfig::new(target)).unwrap();
+        let data: Vec<u8> = (0..2000).map(|x| x as u8).collect();
+        sender.send(&data).unwrap();
+        let (_, first) = listener.recv().unwrap();
+        let (_, second) = listener.recv().unwrap();
+        assert_eq!((first.frame_counter, second.frame_counter), (0, 1));
+        assert_eq!([first.data, second.data].concat(), data);
+    }
+}
@@ -0,0 +1,344 @@
+// VBAN-SERVICE, of which PING0 is implemented to identify and discover
+// devices on the network
+use std::collections::HashMap;
+use std::env;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::error::{Result, malformed};
+use crate::header::{SubProtocol, VBAN_HEADER_SIZE, VbanHeader};
+use crate::net::SenderSockets;
+
+/// Stream name service packets are sent with
+pub const SERVICE_STREAM_NAME: &str = "VBAN Service";
+
+/// Size of the PING0 payload
+pub const PING0_SIZE: usize = 676;
+
+const SERVICE_IDENTIFICATION: u8 = 0;
+const FUNCTION_PING0: u8 = 0;
+const FUNCTION_REPLY: u8 = 0x80;
+
+/// Bits of `Identity::device_type`
+pub mod device_type {
+    pub const RECEPTOR: u32 = 0x01;
+    pub const TRANSMITTER: u32 = 0x02;
+    pub const RECEPTOR_SPOT: u32 = 0x04;
+    pub const TRANSMITTER_SPOT: u32 = 0x08;
+    pub const VIRTUAL_DEVICE: u32 = 0x10;
+    pub const VIRTUAL_MIXER: u32 = 0x20;
+    pub const MATRIX: u32 = 0x40;
+    pub const DAW: u32 = 0x80;
+}
+
+/// Bits of `Identity::features`
+pub mod features {
+    pub const AUDIO: u32 = 0x01;
+    pub const AOIP: u32 = 0x02;
+    pub const VOIP: u32 = 0x04;
+    pub const SERIAL: u32 = 0x100;
+    pub const MIDI: u32 = 0x300;
+    pub const FRAME: u32 = 0x1000;
+    pub const TEXT: u32 = 0x10000;
+}
+
+/// What a device tells about itself in a PING0 request or reply. Strings
+/// longer than their field are truncated when sent.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Identity {
+    pub device_type: u32,
+    pub features: u32,
+    pub features_ex: u32,
+    pub preferred_rate: u32,
+    pub min_rate: u32,
+    pub max_rate: u32,
+    /// The colour to show the device with, as 0xRRGGBB
+    pub color: u32,
+    pub version: [u8; 4],
+    pub gps_position: String,
+    pub user_position: String,
+    /// Language code such as `en-US`
+    pub language: String,
+    pub distant_ip: String,
+    pub distant_port: u16,
+    pub device_name: String,
+    pub manufacturer_name: String,
+    pub application_name: String,
+    pub host_name: String,
+    pub user_name: String,
+    pub user_comment: String,
+}
+
+impl Identity {
+    /// The identity of this program on this machine
+    pub fn local(device_type: u32, features: u32) -> Self {
+        let host_name = gethostname::gethostname().to_string_lossy().into_owned();
+        let version = [
+            env!("CARGO_PKG_VERSION_MAJOR"),
+            env!("CARGO_PKG_VERSION_MINOR"),
+            env!("CARGO_PKG_VERSION_PATCH"),
+        ]
+        .map(|x| x.parse().unwrap_or(0));
+        Self {
+            device_type,
+            features,
+            version: [version[0], version[1], version[2], 0],
+            // e.g. en_US.UTF-8
+            language: env::var("LANG")
+                .ok()
+                .and_then(|x| x.split('.').next().map(|x| x.replace('_', "-")))
+                .unwrap_or_default(),
+            device_name: host_name.clone(),
+            application_name: env!("CARGO_PKG_NAME").to_string(),
+            host_name,
+            user_name: env::var("USER")
+                .or_else(|_| env::var("USERNAME"))
+                .unwrap_or_default(),
+            ..Default::default()
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(PING0_SIZE);
+        for x in [
+            self.device_type,
+            self.features,
+            self.features_ex,
+            self.preferred_rate,
+            self.min_rate,
+            self.max_rate,
+            self.color,
+        ] {
+            bytes.extend_from_slice(&x.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.version);
+        put_str(&mut bytes, &self.gps_position, 8);
+        put_str(&mut bytes, &self.user_position, 8);
+        put_str(&mut bytes, &self.language, 8);
+        // reserved
+        bytes.resize(bytes.len() + 8 + 64, 0);
+        put_str(&mut bytes, &self.distant_ip, 32);
+        bytes.extend_from_slice(&self.distant_port.to_le_bytes());
+        bytes.extend_from_slice(&[0; 2]);
+        put_str(&mut bytes, &self.device_name, 64);
+        put_str(&mut bytes, &self.manufacturer_name, 64);
+        put_str(&mut bytes, &self.application_name, 64);
+        put_str(&mut bytes, &self.host_name, 64);
+        put_str(&mut bytes, &self.user_name, 128);
+        put_str(&mut bytes, &self.user_comment, 128);
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < PING0_SIZE {
+            malformed!("PING0 payload too short: {} bytes", bytes.len());
+        }
+        let u32_at = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        Ok(Self {
+            device_type: u32_at(0),
+            features: u32_at(4),
+            features_ex: u32_at(8),
+            preferred_rate: u32_at(12),
+            min_rate: u32_at(16),
+            max_rate: u32_at(20),
+            color: u32_at(24),
+            version: bytes[28..32].try_into().unwrap(),
+            gps_position: get_str(&bytes[32..40]),
+            user_position: get_str(&bytes[40..48]),
+            language: get_str(&bytes[48..56]),
+            distant_ip: get_str(&bytes[128..160]),
+            distant_port: u16::from_le_bytes([bytes[160], bytes[161]]),
+            device_name: get_str(&bytes[164..228]),
+            manufacturer_name: get_str(&bytes[228..292]),
+            application_name: get_str(&bytes[292..356]),
+            host_name: get_str(&bytes[356..420]),
+            user_name: get_str(&bytes[420..548]),
+            user_comment: get_str(&bytes[548..676]),
+        })
+    }
+}
+
+/// Appends `s` as a null-padded field of `len` bytes, cut at a character
+/// boundary if too long
+fn put_str(out: &mut Vec<u8>, s: &str, len: usize) {
+    let mut end = s.len().min(len);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    out.extend_from_slice(&s.as_bytes()[..end]);
+    out.resize(out.len() + len - end, 0);
+}
+
+fn get_str(field: &[u8]) -> String {
+    let len = field.iter().position(|&x| x == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..len]).into_owned()
+}
+
+/// A PING0 request, or the reply to one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PingPacket {
+    pub reply: bool,
+    /// Chosen by the requester and echoed in the reply
+    pub frame_counter: u32,
+    pub identity: Identity,
+}
+
+impl PingPacket {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut header = VbanHeader {
+            sub_protocol: SubProtocol::Service,
+            sample_rate_index: 0,
+            frame_counter: self.frame_counter,
+            ..Default::default()
+        };
+        header.set_stream_name(SERVICE_STREAM_NAME);
+        let mut bytes = header.to_bytes().to_vec();
+        // bytes 5 to 7 hold the function and service type for services
+        bytes[5] = if self.reply {
+            FUNCTION_REPLY
+        } else {
+            FUNCTION_PING0
+        };
+        bytes[6] = SERVICE_IDENTIFICATION;
+        bytes[7] = 0;
+        bytes.extend(self.identity.to_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(packet: &[u8]) -> Result<Self> {
+        let header = VbanHeader::from_bytes(packet)?;
+        if header.sub_protocol != SubProtocol::Service {
+            malformed!("not a service packet: {:?}", header.sub_protocol);
+        }
+        if packet[6] != SERVICE_IDENTIFICATION {
+            malformed!("unsupported service type {}", packet[6]);
+        }
+        let reply = match packet[5] {
+            FUNCTION_PING0 => false,
+            FUNCTION_REPLY => true,
+            x => malformed!("unsupported service function {:#04x}", x),
+        };
+        Ok(Self {
+            reply,
+            frame_counter: header.frame_counter,
+            identity: Identity::from_bytes(&packet[VBAN_HEADER_SIZE..])?,
+        })
+    }
+}
+
+/// The reply to send if `packet` is a PING0 request
+pub(crate) fn answer_ping(packet: &[u8], identity: &Identity) -> Option<Vec<u8>> {
+    let request = PingPacket::from_bytes(packet).ok()?;
+    if request.reply {
+        return None;
+    }
+    let reply = PingPacket {
+        reply: true,
+        frame_counter: request.frame_counter,
+        identity: identity.clone(),
+    };
+    Some(reply.to_bytes())
+}
+
+/// Sends a PING0 request to `target`, usually a broadcast address, and
+/// collects the replies arriving within `timeout`, one per peer
+pub fn discover(
+    target: SocketAddr,
+    identity: &Identity,
+    timeout: Duration,
+) -> Result<Vec<(SocketAddr, Identity)>> {
+    let mut sockets = SenderSockets::new(None, 1);
+    let request = PingPacket {
+        reply: false,
+        frame_counter: 0,
+        identity: identity.clone(),
+    };
+    sockets.send_to(&request.to_bytes(), target)?;
+
+    let socket = sockets.socket_for(target)?;
+    let mut peers = HashMap::new();
+    let mut buffer = vec![0u8; 65536];
+    let deadline = Instant::now() + timeout;
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now())
+        && !remaining.is_zero()
+    {
+        socket.set_read_timeout(Some(remaining))?;
+        match socket.recv_from(&mut buffer) {
+            Ok((amt, source)) => {
+                if let Ok(packet) = PingPacket::from_bytes(&buffer[..amt])
+                    && packet.reply
+                {
+                    let source = SocketAddr::new(source.ip().to_canonical(), source.port());
+                    peers.insert(source, packet.identity);
+                }
+            }
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) =>
+            {
+                break;
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+    let mut peers: Vec<_> = peers.into_iter().collect();
+    peers.sort_by_key(|(x, _)| *x);
+    Ok(peers)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::UdpSocket;
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn round_trips_ping() {
+        let packet = PingPacket {
+            reply: true,
+            frame_counter: 9,
+            identity: Identity {
+                color: 0x00FF8000,
+                language: String::from("fr-FR"),
+                distant_port: 6980,
+                user_comment: String::from("régie"),
+                ..Identity::local(device_type::RECEPTOR, features::AUDIO | features::TEXT)
+            },
+        };
+        let bytes = packet.to_bytes();
+        assert_eq!(bytes.len(), VBAN_HEADER_SIZE + PING0_SIZE);
+        assert_eq!((bytes[4], bytes[5], bytes[6]), (0x60, 0x80, 0));
+        assert_eq!(PingPacket::from_bytes(&bytes).unwrap(), packet);
+    }
+
+    #[test]
+    fn truncates_long_fields_on_character_boundaries() {
+        let mut field = Vec::new();
+        put_str(&mut field, &"é".repeat(8), 7);
+        assert_eq!(field.len(), 7);
+        assert_eq!(get_str(&field), "ééé");
+    }
+
+    #[test]
+    fn discovers_answering_peer() {
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let target = peer.local_addr().unwrap();
+        let identity = Identity {
+            device_name: String::from("Peer"),
+            ..Default::default()
+        };
+        let answering = identity.clone();
+        let responder = thread::spawn(move || {
+            let mut buffer = [0u8; 2048];
+            let (amt, source) = peer.recv_from(&mut buffer).unwrap();
+            let reply = answer_ping(&buffer[..amt], &answering).unwrap();
+            peer.send_to(&reply, source).unwrap();
+        });
+        let peers = discover(target, &Identity::default(), Duration::from_millis(500)).unwrap();
+        responder.join().unwrap();
+        assert_eq!(peers, vec![(target, identity)]);
+    }
+}
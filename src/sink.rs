@@ -0,0 +1,200 @@
+// Outputs the receiver plays to
+use std::any::Any;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use cpal::traits::{DeviceTrait, StreamTrait};
+use cpal::{Device, SampleRate, SupportedStreamConfig};
+
+use crate::device;
+use crate::error::Result;
+
+/// Writes the interleaved samples to play into its argument
+pub type FillCallback = Box<dyn FnMut(&mut [f32]) + Send>;
+
+/// Plays interleaved audio pulled from a callback
+pub trait AudioSink: Send {
+    /// Starts pulling audio from `fill`, at `rate` if the sink supports it.
+    /// The sink plays until the returned stream is dropped.
+    fn open(&self, rate: SampleRate, fill: FillCallback) -> Result<SinkStream>;
+}
+
+/// A playing sink, stopped when dropped
+pub struct SinkStream {
+    rate: SampleRate,
+    channels: usize,
+    _stream: Box<dyn Any>,
+}
+
+impl SinkStream {
+    pub fn rate(&self) -> SampleRate {
+        self.rate
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+}
+
+/// Plays on a cpal output device
+pub struct DeviceSink {
+    device: Device,
+}
+
+impl DeviceSink {
+    pub fn new(device: Device) -> Self {
+        Self { device }
+    }
+}
+
+impl AudioSink for DeviceSink {
+    /// Opens the device at `rate` if it supports it, at its default rate
+    /// otherwise
+    fn open(&self, rate: SampleRate, fill: FillCallback) -> Result<SinkStream> {
+        let config = output_config(&self.device, rate)?;
+        let stream = device::build_output_stream(
+            &self.device,
+            &config.config(),
+            config.sample_format(),
+            fill,
+        )?;
+        stream.play()?;
+        Ok(SinkStream {
+            rate: config.sample_rate(),
+            channels: config.channels() as usize,
+            _stream: Box::new(stream),
+        })
+    }
+}
+
+/// Picks the output config for a stream rate, falling back to the device
+/// default when the device cannot run at that rate
+fn output_config(device: &Device, rate: SampleRate) -> Result<SupportedStreamConfig> {
+    let default_config = device.default_output_config()?;
+    let supported = device
+        .supported_output_configs()?
+        .filter(|x| {
+            x.channels() == default_config.channels()
+                && x.min_sample_rate() <= rate
+                && rate <= x.max_sample_rate()
+        })
+        // prefer the sample format the device defaults to
+        .max_by_key(|x| x.sample_format() == default_config.sample_format());
+    Ok(match supported {
+        Some(x) => x.with_sample_rate(rate),
+        None => default_config,
+    })
+}
+
+/// Pulls audio in 10ms blocks on a thread paced by the sample rate, standing
+/// in for a sound card clock
+struct PacedStream {
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl PacedStream {
+    fn start(
+        rate: SampleRate,
+        channels: usize,
+        mut fill: FillCallback,
+        mut on_block: impl FnMut(&[f32]) + Send + 'static,
+    ) -> Self {
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = running.clone();
+        let thread = thread::spawn(move || {
+            let frames = (rate.0 as usize / 100).max(1);
+            let mut block = vec![0.0; frames * channels];
+            let start = Instant::now();
+            let mut frames_played = 0u64;
+            while thread_running.load(Ordering::Relaxed) {
+                let due = start + Duration::from_secs_f64(frames_played as f64 / rate.0 as f64);
+                if let Some(wait) = due.checked_duration_since(Instant::now()) {
+                    thread::sleep(wait);
+                }
+                fill(&mut block);
+                on_block(&block);
+                frames_played += frames as u64;
+            }
+        });
+        Self {
+            running,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for PacedStream {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Discards everything at the rate of a sound card, for machines without one
+pub struct NullSink {
+    channels: usize,
+}
+
+impl NullSink {
+    pub fn new(channels: usize) -> Self {
+        Self {
+            channels: channels.max(1),
+        }
+    }
+}
+
+impl AudioSink for NullSink {
+    fn open(&self, rate: SampleRate, fill: FillCallback) -> Result<SinkStream> {
+        Ok(SinkStream {
+            rate,
+            channels: self.channels,
+            _stream: Box::new(PacedStream::start(rate, self.channels, fill, |_| {})),
+        })
+    }
+}
+
+/// Keeps everything played in memory, paced like a sound card. Clones share
+/// the same buffer, so one can be handed to a receiver and the other read.
+#[derive(Clone)]
+pub struct MemorySink {
+    channels: usize,
+    samples: Arc<Mutex<Vec<f32>>>,
+}
+
+impl MemorySink {
+    pub fn new(channels: usize) -> Self {
+        Self {
+            channels: channels.max(1),
+            samples: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Takes the interleaved samples played since the last call
+    pub fn take(&self) -> Vec<f32> {
+        self.samples
+            .lock()
+            .map(|mut x| std::mem::take(&mut *x))
+            .unwrap_or_default()
+    }
+}
+
+impl AudioSink for MemorySink {
+    fn open(&self, rate: SampleRate, fill: FillCallback) -> Result<SinkStream> {
+        let samples = self.samples.clone();
+        let on_block = move |block: &[f32]| {
+            if let Ok(mut samples) = samples.lock() {
+                samples.extend_from_slice(block);
+            }
+        };
+        Ok(SinkStream {
+            rate,
+            channels: self.channels,
+            _stream: Box::new(PacedStream::start(rate, self.channels, fill, on_block)),
+        })
+    }
+}
@@ -0,0 +1,474 @@
+// Sources of the audio a sender transmits
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use cpal::traits::{DeviceTrait, StreamTrait};
+use cpal::{Device, SampleRate, Stream, SupportedStreamConfig};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{CODEC_TYPE_NULL, Decoder, DecoderOptions};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+use crate::device;
+use crate::error::{Result, VbanError, io_error, unsupported};
+
+/// Receives interleaved samples from a running source
+pub type DataCallback = Box<dyn FnMut(&[f32]) + Send>;
+
+/// Produces interleaved audio at a fixed rate and channel count
+pub trait AudioSource {
+    fn sample_rate(&self) -> SampleRate;
+    fn channels(&self) -> usize;
+    /// Starts handing audio to `on_data` as it becomes available. Dropping
+    /// `on_data` tells the consumer that the source has ended.
+    fn start(&mut self, on_data: DataCallback) -> Result<()>;
+    fn stop(&mut self);
+}
+
+/// Captures from a cpal input device in its default config
+pub struct DeviceSource {
+    device: Device,
+    config: SupportedStreamConfig,
+    stream: Option<Stream>,
+}
+
+impl DeviceSource {
+    pub fn new(device: Device) -> Result<Self> {
+        let config = device.default_input_config()?;
+        Ok(Self {
+            device,
+            config,
+            stream: None,
+        })
+    }
+}
+
+impl AudioSource for DeviceSource {
+    fn sample_rate(&self) -> SampleRate {
+        self.config.sample_rate()
+    }
+
+    fn channels(&self) -> usize {
+        self.config.channels() as usize
+    }
+
+    fn start(&mut self, on_data: DataCallback) -> Result<()> {
+        let stream = device::build_input_stream(
+            &self.device,
+            &self.config.config(),
+            self.config.sample_format(),
+            on_data,
+        )?;
+        stream.play()?;
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        if let Some(stream) = self.stream.take() {
+            let _ = stream.pause();
+        }
+    }
+}
+
+/// Decodes an audio file one packet at a time
+pub(crate) struct FileDecoder {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    pub(crate) rate: SampleRate,
+    pub(crate) channels: usize,
+}
+
+impl FileDecoder {
+    pub(crate) fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path)
+            .map_err(|err| io_error(err, format_args!("Failed to open '{}'", path.display())))?;
+        let stream = MediaSourceStream::new(Box::new(file), Default::default());
+        let mut hint = Hint::new();
+        if let Some(extension) = path.extension().and_then(|x| x.to_str()) {
+            hint.with_extension(extension);
+        }
+        let probed = symphonia::default::get_probe()
+            .format(
+                &hint,
+                stream,
+                &FormatOptions::default(),
+                &MetadataOptions::default(),
+            )
+            .map_err(|err| {
+                VbanError::UnsupportedConfig(format!(
+                    "Unsupported audio file '{}': {}",
+                    path.display(),
+                    err
+                ))
+            })?;
+        let format = probed.format;
+        let track = format
+            .tracks()
+            .iter()
+            .find(|x| x.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or_else(|| {
+                VbanError::UnsupportedConfig(format!("No audio track in '{}'", path.display()))
+            })?;
+        let rate = track.codec_params.sample_rate.ok_or_else(|| {
+            VbanError::UnsupportedConfig(format!("Unknown sample rate in '{}'", path.display()))
+        })?;
+        let channels = track
+            .codec_params
+            .channels
+            .ok_or_else(|| {
+                VbanError::UnsupportedConfig(format!(
+                    "Unknown channel layout in '{}'",
+                    path.display()
+                ))
+            })?
+            .count();
+        let decoder = symphonia::default::get_codecs()
+            .make(&track.codec_params, &DecoderOptions::default())
+            .map_err(|err| {
+                VbanError::UnsupportedConfig(format!(
+                    "Unsupported codec in '{}': {}",
+                    path.display(),
+                    err
+                ))
+            })?;
+        Ok(Self {
+            track_id: track.id,
+            format,
+            decoder,
+            rate: SampleRate(rate),
+            channels,
+        })
+    }
+
+    /// The next decoded interleaved samples, `None` at the end of the file
+    pub(crate) fn next(&mut self) -> Result<Option<Vec<f32>>> {
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(x) => x,
+                Err(SymphoniaError::IoError(err))
+                    if err.kind() == std::io::ErrorKind::UnexpectedEof =>
+                {
+                    return Ok(None);
+                }
+                Err(err) => return Err(decode_error(err)),
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+            match self.decoder.decode(&packet) {
+                Ok(decoded) => {
+                    let mut buffer =
+                        SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
+                    buffer.copy_interleaved_ref(decoded);
+                    return Ok(Some(buffer.samples().to_vec()));
+                }
+                // skip corrupted packets
+                Err(SymphoniaError::DecodeError(_)) => continue,
+                Err(err) => return Err(decode_error(err)),
+            }
+        }
+    }
+}
+
+fn decode_error(err: SymphoniaError) -> VbanError {
+    match err {
+        SymphoniaError::IoError(err) => VbanError::Io(err),
+        SymphoniaError::Unsupported(feature) => {
+            VbanError::UnsupportedConfig(format!("Unsupported feature: {}", feature))
+        }
+        err => VbanError::Stream(err.to_string()),
+    }
+}
+
+/// Hands the blocks `next_block` produces to `on_data` on schedule for
+/// `rate`, as a sound card would, until `running` is cleared or `next_block`
+/// runs out. `next_block` is asked for up to the given number of samples.
+fn spawn_paced(
+    rate: SampleRate,
+    channels: usize,
+    running: Arc<AtomicBool>,
+    mut next_block: impl FnMut(usize) -> Option<Vec<f32>> + Send + 'static,
+    mut on_data: DataCallback,
+) -> JoinHandle<()> {
+    // hand out audio in 10ms blocks
+    let block = (rate.0 as usize / 100).max(1) * channels;
+    running.store(true, Ordering::Relaxed);
+    thread::spawn(move || {
+        let start = Instant::now();
+        let mut frames_sent = 0u64;
+        while running.load(Ordering::Relaxed) {
+            let Some(samples) = next_block(block).filter(|x| !x.is_empty()) else {
+                break;
+            };
+            let due = start + Duration::from_secs_f64(frames_sent as f64 / rate.0 as f64);
+            if let Some(wait) = due.checked_duration_since(Instant::now()) {
+                thread::sleep(wait);
+            }
+            on_data(&samples);
+            frames_sent += (samples.len() / channels) as u64;
+        }
+    })
+}
+
+/// Plays a WAV, FLAC or Ogg Vorbis file in real time, paced by its sample
+/// rate rather than by a sound card, optionally starting over at the end
+pub struct FileSource {
+    path: PathBuf,
+    looping: bool,
+    decoder: Option<FileDecoder>,
+    rate: SampleRate,
+    channels: usize,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl FileSource {
+    pub fn open(path: impl Into<PathBuf>, looping: bool) -> Result<Self> {
+        let path = path.into();
+        let decoder = FileDecoder::open(&path)?;
+        Ok(Self {
+            path,
+            looping,
+            rate: decoder.rate,
+            channels: decoder.channels,
+            decoder: Some(decoder),
+            running: Arc::new(AtomicBool::new(false)),
+            thread: None,
+        })
+    }
+}
+
+impl AudioSource for FileSource {
+    fn sample_rate(&self) -> SampleRate {
+        self.rate
+    }
+
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn start(&mut self, on_data: DataCallback) -> Result<()> {
+        let mut decoder = self.decoder.take().ok_or_else(|| {
+            VbanError::Stream(format!("'{}' is already playing", self.path.display()))
+        })?;
+        let path = self.path.clone();
+        let looping = self.looping;
+        let rate = self.rate;
+        let channels = self.channels;
+        let mut pending = Vec::new();
+        let mut pass_empty = true;
+        let next_block = move |block: usize| {
+            while pending.len() < block {
+                match decoder.next() {
+                    Ok(Some(samples)) => {
+                        pass_empty &= samples.is_empty();
+                        pending.extend(samples);
+                    }
+                    // starting over a file without audio would spin
+                    Ok(None) if looping && !pass_empty => {
+                        match FileDecoder::open(&path).and_then(|x| {
+                            if x.rate != rate || x.channels != channels {
+                                unsupported!("'{}' changed its format", path.display());
+                            }
+                            Ok(x)
+                        }) {
+                            Ok(x) => decoder = x,
+                            Err(err) => {
+                                eprintln!("{}", err);
+                                break;
+                            }
+                        }
+                        pass_empty = true;
+                    }
+                    Ok(None) => break,
+                    Err(err) => {
+                        eprintln!("Failed to decode '{}': {}", path.display(), err);
+                        break;
+                    }
+                }
+            }
+            let len = pending.len().min(block);
+            Some(pending.drain(..len).collect())
+        };
+        self.thread = Some(spawn_paced(
+            rate,
+            channels,
+            self.running.clone(),
+            next_block,
+            on_data,
+        ));
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// The test signals a `GeneratorSource` produces
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Waveform {
+    /// A sine at -6 dBFS on every channel
+    #[default]
+    Sine,
+    /// White noise at -6 dBFS peak, independent per channel
+    Noise,
+    /// Digital silence on every channel
+    Silence,
+}
+
+/// Generates a test signal in real time, standing in for a capture device
+/// on machines without one
+pub struct GeneratorSource {
+    waveform: Waveform,
+    frequency: f32,
+    rate: SampleRate,
+    channels: usize,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl GeneratorSource {
+    /// A generator of `waveform`, where `frequency` is the sine frequency in
+    /// Hz
+    pub fn new(waveform: Waveform, frequency: f32, rate: SampleRate, channels: usize) -> Self {
+        Self {
+            waveform,
+            frequency,
+            rate,
+            channels: channels.max(1),
+            running: Arc::new(AtomicBool::new(false)),
+            thread: None,
+        }
+    }
+}
+
+impl AudioSource for GeneratorSource {
+    fn sample_rate(&self) -> SampleRate {
+        self.rate
+    }
+
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn start(&mut self, on_data: DataCallback) -> Result<()> {
+        if self.thread.is_some() {
+            return Err(VbanError::Stream(String::from(
+                "The generator is already running",
+            )));
+        }
+        let waveform = self.waveform;
+        let step = std::f64::consts::TAU * self.frequency as f64 / self.rate.0 as f64;
+        let channels = self.channels;
+        let mut frame = 0u64;
+        // xorshift, plenty for test noise
+        let mut seed = 0x2545_F491u32;
+        let next_block = move |block: usize| {
+            let mut samples = Vec::with_capacity(block);
+            for _ in 0..block / channels {
+                let sine = ((frame as f64 * step).sin() * 0.5) as f32;
+                for _ in 0..channels {
+                    samples.push(match waveform {
+                        Waveform::Sine => sine,
+                        Waveform::Noise => {
+                            seed ^= seed << 13;
+                            seed ^= seed >> 17;
+                            seed ^= seed << 5;
+                            seed as f32 / u32::MAX as f32 - 0.5
+                        }
+                        Waveform::Silence => 0.0,
+                    });
+                }
+                frame += 1;
+            }
+            Some(samples)
+        };
+        self.thread = Some(spawn_paced(
+            self.rate,
+            channels,
+            self.running.clone(),
+            next_block,
+            on_data,
+        ));
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Plays interleaved samples held in memory once, in real time
+pub struct MemorySource {
+    samples: Option<Vec<f32>>,
+    rate: SampleRate,
+    channels: usize,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MemorySource {
+    pub fn new(samples: Vec<f32>, rate: SampleRate, channels: usize) -> Self {
+        Self {
+            samples: Some(samples),
+            rate,
+            channels: channels.max(1),
+            running: Arc::new(AtomicBool::new(false)),
+            thread: None,
+        }
+    }
+}
+
+impl AudioSource for MemorySource {
+    fn sample_rate(&self) -> SampleRate {
+        self.rate
+    }
+
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn start(&mut self, on_data: DataCallback) -> Result<()> {
+        let samples = self
+            .samples
+            .take()
+            .ok_or_else(|| VbanError::Stream(String::from("The buffer is already playing")))?;
+        let mut position = 0;
+        let next_block = move |block: usize| {
+            let len = (samples.len() - position).min(block);
+            position += len;
+            Some(samples[position - len..position].to_vec())
+        };
+        self.thread = Some(spawn_paced(
+            self.rate,
+            self.channels,
+            self.running.clone(),
+            next_block,
+            on_data,
+        ));
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
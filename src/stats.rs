@@ -0,0 +1,29 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Counters for events on the receiving side, updated while the receiver
+/// runs
+#[derive(Debug, Default)]
+pub struct ReceiverStats {
+    /// Times playback ran out of buffered audio
+    pub underruns: AtomicU64,
+    /// Times buffered audio was discarded to keep latency bounded
+    pub overruns: AtomicU64,
+    /// Packets that never arrived and were concealed
+    pub lost: AtomicU64,
+    /// Packets received more than once
+    pub duplicates: AtomicU64,
+    /// Packets that arrived after later ones but in time to be played
+    pub reordered: AtomicU64,
+    /// Packets that arrived after they had already been concealed
+    pub late: AtomicU64,
+}
+
+impl ReceiverStats {
+    pub(crate) fn count(counter: &AtomicU64) {
+        Self::add(counter, 1);
+    }
+
+    pub(crate) fn add(counter: &AtomicU64, n: u64) {
+        counter.fetch_add(n, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,197 @@
+use std::fs;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use clap::ValueEnum;
+
+use crate::error::{Result, VbanError, io_error, unsupported};
+use crate::header::DataFormat;
+
+/// How often the addresses of targets are looked up again, so that a
+/// receiver whose address changes keeps getting the stream
+pub const RESOLVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A destination for transmitted packets.
+///
+/// Written as a host and port optionally followed by overrides of the sender
+/// settings, e.g. `mixer.local:6980,name=Mic,format=i16`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    /// The host and port, where the host is a name or an IP address
+    pub address: String,
+    /// The stream name sent to this target, the sender's if not set
+    pub stream_name: Option<String>,
+    /// The sample format sent to this target, the sender's if not set
+    pub data_format: Option<DataFormat>,
+}
+
+impl Target {
+    pub fn new(address: impl Into<String>) -> Self {
+        Self {
+            address: address.into(),
+            stream_name: None,
+            data_format: None,
+        }
+    }
+
+    /// Looks up the address with the system resolver, taking the first
+    /// result of the same family as `bind_address` if given
+    pub fn resolve(&self, bind_address: Option<SocketAddr>) -> Result<SocketAddr> {
+        self.address
+            .to_socket_addrs()
+            .map_err(|err| io_error(err, format_args!("Failed to resolve '{}'", self.address)))?
+            .find(|x| bind_address.is_none_or(|y| x.is_ipv4() == y.is_ipv4()))
+            .ok_or_else(|| {
+                VbanError::UnsupportedConfig(format!(
+                    "No usable address found for '{}'",
+                    self.address
+                ))
+            })
+    }
+}
+
+impl FromStr for Target {
+    type Err = VbanError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.split(',').map(str::trim);
+        let address = parts.next().unwrap_or_default();
+        if address
+            .rsplit_once(':')
+            .is_none_or(|(host, port)| host.is_empty() || port.parse::<u16>().is_err())
+        {
+            unsupported!("Expected HOST:PORT, got '{}'", address);
+        }
+        let mut target = Target::new(address);
+        for part in parts {
+            match part.split_once('=') {
+                Some(("name", name)) => target.stream_name = Some(name.to_string()),
+                Some(("format", format)) => {
+                    target.data_format = Some(DataFormat::from_str(format, true).map_err(|_| {
+                        VbanError::UnsupportedConfig(format!("Invalid format '{}'", format))
+                    })?)
+                }
+                _ => unsupported!("Expected 'name=NAME' or 'format=FORMAT', got '{}'", part),
+            }
+        }
+        Ok(target)
+    }
+}
+
+/// Reads targets from a file holding one per line, skipping blank lines and
+/// lines starting with `#`
+pub fn read_targets(path: &Path) -> Result<Vec<Target>> {
+    let contents = fs::read_to_string(path).map_err(|err| {
+        io_error(
+            err,
+            format_args!("Failed to read targets from '{}'", path.display()),
+        )
+    })?;
+    contents
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(i, line)| {
+            line.parse().map_err(|err| {
+                VbanError::UnsupportedConfig(format!("{}:{}: {}", path.display(), i + 1, err))
+            })
+        })
+        .collect()
+}
+
+/// Keeps the addresses of targets up to date by resolving them again every
+/// `RESOLVE_INTERVAL` on its own thread, until dropped
+pub(crate) struct Resolver {
+    addresses: Arc<Mutex<Vec<SocketAddr>>>,
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Resolver {
+    /// Resolves every target once, failing if any cannot be resolved
+    pub(crate) fn start(targets: Vec<Target>, bind_address: Option<SocketAddr>) -> Result<Self> {
+        let addresses = targets
+            .iter()
+            .map(|x| x.resolve(bind_address))
+            .collect::<Result<Vec<_>>>()?;
+        let addresses = Arc::new(Mutex::new(addresses));
+        let thread_addresses = addresses.clone();
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = thread::spawn(move || {
+            // the channel disconnects when the resolver is dropped
+            while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(RESOLVE_INTERVAL)
+            {
+                for (i, target) in targets.iter().enumerate() {
+                    // keep the last known address while the lookup fails
+                    match target.resolve(bind_address) {
+                        Ok(address) => {
+                            let mut addresses = thread_addresses.lock().unwrap();
+                            if addresses[i] != address {
+                                println!(
+                                    "Target '{}' moved from {} to {}.",
+                                    target.address, addresses[i], address
+                                );
+                                addresses[i] = address;
+                            }
+                        }
+                        Err(err) => eprintln!("{}", err),
+                    }
+                }
+            }
+        });
+        Ok(Self {
+            addresses,
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+
+    /// The current address of every target, in the order they were given
+    pub(crate) fn addresses(&self) -> Vec<SocketAddr> {
+        self.addresses.lock().unwrap().clone()
+    }
+}
+
+impl Drop for Resolver {
+    fn drop(&mut self) {
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use super::*;
+
+    #[test]
+    fn parses_overrides() {
+        let target: Target = "mixer.local:6980,name=Mic,format=i16".parse().unwrap();
+        assert_eq!(target.address, "mixer.local:6980");
+        assert_eq!(target.stream_name.as_deref(), Some("Mic"));
+        assert_eq!(target.data_format, Some(DataFormat::I16));
+        assert!("mixer.local".parse::<Target>().is_err());
+        assert!("mixer.local:6980,rate=48000".parse::<Target>().is_err());
+    }
+
+    #[test]
+    fn resolves_hostnames() {
+        let target = Target::new("localhost:6980");
+        let v4 = SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0));
+        assert_eq!(
+            target.resolve(Some(v4)).unwrap(),
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 6980)
+        );
+        let ipv6 = Target::new("[::1]:6980");
+        assert!(ipv6.resolve(None).unwrap().is_ipv6());
+        assert!(ipv6.resolve(Some(v4)).is_err());
+    }
+}
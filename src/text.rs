@@ -0,0 +1,291 @@
+// VBAN-TEXT, used to send commands such as `Strip[0].Mute = 1;` to
+// Voicemeeter
+use std::net::SocketAddr;
+
+use crate::error::{Result, VbanError, malformed, unsupported};
+use crate::header::{
+    SubProtocol, VBAN_HEADER_SIZE, VBAN_MAX_PAYLOAD_SIZE, VBAN_STREAM_NAME_SIZE, VbanHeader,
+    bit_rate_index,
+};
+use crate::net::{self, SenderSockets};
+use crate::target::Target;
+
+const STREAM_TYPE_MASK: u8 = 0xF0;
+
+/// The character encoding of a text stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum TextFormat {
+    Ascii,
+    #[default]
+    Utf8,
+    /// UTF-16, little-endian
+    Wchar,
+}
+
+impl TextFormat {
+    fn from_bits(bits: u8) -> Option<Self> {
+        match bits & STREAM_TYPE_MASK {
+            0x00 => Some(TextFormat::Ascii),
+            0x10 => Some(TextFormat::Utf8),
+            0x20 => Some(TextFormat::Wchar),
+            _ => None,
+        }
+    }
+
+    fn bits(self) -> u8 {
+        match self {
+            TextFormat::Ascii => 0x00,
+            TextFormat::Utf8 => 0x10,
+            TextFormat::Wchar => 0x20,
+        }
+    }
+
+    fn encode(self, text: &str) -> Vec<u8> {
+        match self {
+            // characters outside ASCII are replaced rather than sent as UTF-8
+            TextFormat::Ascii => text
+                .chars()
+                .map(|c| if c.is_ascii() { c as u8 } else { b'?' })
+                .collect(),
+            TextFormat::Utf8 => text.as_bytes().to_vec(),
+            TextFormat::Wchar => text.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+        }
+    }
+
+    fn decode(self, payload: &[u8]) -> String {
+        match self {
+            TextFormat::Ascii | TextFormat::Utf8 => String::from_utf8_lossy(payload).into_owned(),
+            TextFormat::Wchar => {
+                let units: Vec<u16> = payload
+                    .chunks_exact(2)
+                    .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                    .collect();
+                String::from_utf16_lossy(&units)
+            }
+        }
+    }
+}
+
+/// A VBAN-TEXT packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextPacket {
+    pub stream_name: String,
+    /// Index into `VBAN_BIT_RATES`
+    pub bit_rate_index: u8,
+    /// Identifies the channel within the stream
+    pub channel: u8,
+    pub format: TextFormat,
+    pub frame_counter: u32,
+    /// The text, without any trailing null bytes
+    pub text: String,
+}
+
+impl TextPacket {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut header = VbanHeader {
+            sub_protocol: SubProtocol::Text,
+            sample_rate_index: self.bit_rate_index,
+            frame_counter: self.frame_counter,
+            ..Default::default()
+        };
+        header.set_stream_name(&self.stream_name);
+        let mut bytes = header.to_bytes().to_vec();
+        // the audio fields of bytes 5 to 7 mean something else for text
+        bytes[5] = 0;
+        bytes[6] = self.channel;
+        bytes[7] = self.format.bits();
+        bytes.extend(self.format.encode(&self.text));
+        bytes
+    }
+
+    pub fn from_bytes(packet: &[u8]) -> Result<Self> {
+        let header = VbanHeader::from_bytes(packet)?;
+        if header.sub_protocol != SubProtocol::Text {
+            malformed!("not a text packet: {:?}", header.sub_protocol);
+        }
+        let format = TextFormat::from_bits(packet[7]).ok_or_else(|| {
+            VbanError::MalformedPacket(format!("unknown text stream type {:#04x}", packet[7]))
+        })?;
+        let text = format.decode(&packet[VBAN_HEADER_SIZE..]);
+        Ok(Self {
+            stream_name: header.stream_name(),
+            bit_rate_index: header.sample_rate_index,
+            channel: packet[6],
+            format,
+            frame_counter: header.frame_counter,
+            text: text.trim_end_matches('\0').to_string(),
+        })
+    }
+}
+
+/// Splits `text` into pieces that each fit in one packet once encoded,
+/// without breaking characters apart
+fn split_text(text: &str, format: TextFormat) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    let mut size = 0;
+    for (i, c) in text.char_indices() {
+        let len = match format {
+            TextFormat::Ascii => 1,
+            TextFormat::Utf8 => c.len_utf8(),
+            TextFormat::Wchar => c.len_utf16() * 2,
+        };
+        if size + len > VBAN_MAX_PAYLOAD_SIZE {
+            pieces.push(&text[start..i]);
+            start = i;
+            size = 0;
+        }
+        size += len;
+    }
+    pieces.push(&text[start..]);
+    pieces
+}
+
+#[derive(Debug, Clone)]
+pub struct TextSenderConfig {
+    /// Where to send the text
+    pub target: Target,
+    /// The VBAN stream name, `Command1` being what Voicemeeter listens to
+    pub stream_name: String,
+    pub bit_rate: u32,
+    pub channel: u8,
+    pub format: TextFormat,
+    /// The address to send from, an ephemeral port of the target's address
+    /// family if not set
+    pub bind_address: Option<SocketAddr>,
+}
+
+impl TextSenderConfig {
+    pub fn new(target: Target) -> Self {
+        Self {
+            target,
+            stream_name: String::from("Command1"),
+            bit_rate: 0,
+            channel: 0,
+            format: TextFormat::default(),
+            bind_address: None,
+        }
+    }
+}
+
+/// Sends text as VBAN-TEXT packets, splitting text too long for one packet
+pub struct TextSender {
+    packet: TextPacket,
+    target: SocketAddr,
+    sockets: SenderSockets,
+}
+
+impl TextSender {
+    pub fn new(config: TextSenderConfig) -> Result<Self> {
+        if config.stream_name.len() > VBAN_STREAM_NAME_SIZE {
+            unsupported!(
+                "Stream name '{}' is longer than {} bytes",
+                config.stream_name,
+                VBAN_STREAM_NAME_SIZE
+            );
+        }
+        let bit_rate_index = bit_rate_index(config.bit_rate).ok_or_else(|| {
+            VbanError::UnsupportedConfig(format!(
+                "Bit rate {} is not supported by VBAN",
+                config.bit_rate
+            ))
+        })?;
+        let target = config.target.resolve(config.bind_address)?;
+        let mut sockets = SenderSockets::new(config.bind_address, 1);
+        sockets.socket_for(target)?;
+        Ok(Self {
+            packet: TextPacket {
+                stream_name: config.stream_name,
+                bit_rate_index,
+                channel: config.channel,
+                format: config.format,
+                frame_counter: 0,
+                text: String::new(),
+            },
+            target,
+            sockets,
+        })
+    }
+
+    pub fn send(&mut self, text: &str) -> Result<()> {
+        let format = self.packet.format;
+        for piece in split_text(text, format) {
+            self.packet.text = piece.to_string();
+            self.sockets.send_to(&self.packet.to_bytes(), self.target)?;
+            self.packet.frame_counter = self.packet.frame_counter.wrapping_add(1);
+        }
+        Ok(())
+    }
+}
+
+/// Receives VBAN-TEXT packets, ignoring packets of other sub-protocols
+pub struct TextListener {
+    socket: std::net::UdpSocket,
+    buffer: Vec<u8>,
+}
+
+impl TextListener {
+    pub fn bind(bind_address: SocketAddr) -> Result<Self> {
+        Ok(Self {
+            socket: net::receiver_socket(bind_address, None, None)?,
+            buffer: vec![0u8; 65536],
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// Waits for the next text packet and the address it came from
+    pub fn recv(&mut self) -> Result<(SocketAddr, TextPacket)> {
+        loop {
+            let (amt, source) = self.socket.recv_from(&mut self.buffer)?;
+            if let Ok(packet) = TextPacket::from_bytes(&self.buffer[..amt]) {
+                let source = SocketAddr::new(source.ip().to_canonical(), source.port());
+                return Ok((source, packet));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_every_format() {
+        for format in [TextFormat::Ascii, TextFormat::Utf8, TextFormat::Wchar] {
+            let packet = TextPacket {
+                stream_name: String::from("Command1"),
+                bit_rate_index: 18,
+                channel: 2,
+                format,
+                frame_counter: 7,
+                text: String::from("Strip[0].Mute = 1;"),
+            };
+            let bytes = packet.to_bytes();
+            assert_eq!(bytes[4], 0x40 | 18);
+            assert_eq!(TextPacket::from_bytes(&bytes).unwrap(), packet);
+        }
+    }
+
+    #[test]
+    fn splits_long_text_on_character_boundaries() {
+        let text = "é".repeat(VBAN_MAX_PAYLOAD_SIZE);
+        let pieces = split_text(&text, TextFormat::Utf8);
+        assert_eq!(pieces.len(), 2);
+        assert!(pieces.iter().all(|x| x.len() <= VBAN_MAX_PAYLOAD_SIZE));
+        assert_eq!(pieces.concat(), text);
+    }
+
+    #[test]
+    fn sends_to_listener() {
+        let mut listener = TextListener::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let target = Target::new(listener.local_addr().unwrap().to_string());
+        let mut sender = TextSender::new(TextSenderConfig::new(target)).unwrap();
+        sender.send("Bus[0].Gain = -6;").unwrap();
+        let (_, packet) = listener.recv().unwrap();
+        assert_eq!(packet.stream_name, "Command1");
+        assert_eq!(packet.text, "Bus[0].Gain = -6;");
+    }
+}
@@ -0,0 +1,104 @@
+// The serial bridge of the CLI, run between a FIFO, a file and a UDP socket
+// standing in for the VBAN peer
+#![cfg(unix)]
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::net::{SocketAddr, UdpSocket};
+use std::process::{Child, Command};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use vban::{SerialFormat, SerialPacket};
+
+/// Kills the bridge when the test ends, however it ends
+struct Bridge(Child);
+
+impl Drop for Bridge {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// The data of every serial packet arriving at `socket` until it has been
+/// quiet for its read timeout
+fn receive_all(socket: &UdpSocket) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut buffer = [0u8; 2048];
+    while let Ok(amt) = socket.recv(&mut buffer) {
+        data.extend(SerialPacket::from_bytes(&buffer[..amt]).unwrap().data);
+    }
+    data
+}
+
+#[test]
+fn received_bytes_are_not_sent_back() {
+    let dir = std::env::temp_dir().join(format!("vban-bridge-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("input");
+    let output = dir.join("output");
+    assert!(
+        Command::new("mkfifo")
+            .arg(&input)
+            .status()
+            .unwrap()
+            .success()
+    );
+
+    let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+    peer.set_read_timeout(Some(Duration::from_millis(500)))
+        .unwrap();
+    let bind_address: SocketAddr = UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let _bridge = Bridge(
+        Command::new(env!("CARGO_BIN_EXE_rust-vban"))
+            .arg("serial")
+            .arg("--input")
+            .arg(&input)
+            .arg("--output")
+            .arg(&output)
+            .arg("--target")
+            .arg(peer.local_addr().unwrap().to_string())
+            .arg("--bind-address")
+            .arg(bind_address.to_string())
+            .spawn()
+            .unwrap(),
+    );
+    // kept open so that the bridge is still reading while data arrives
+    let mut device = OpenOptions::new().write(true).open(&input).unwrap();
+    device.write_all(b"from the device").unwrap();
+
+    // the listener is bound before anything is sent, so once the input
+    // arrives the bridge is ready to receive
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let mut sent = Vec::new();
+    while sent.len() < b"from the device".len() {
+        assert!(Instant::now() < deadline, "the input was never sent");
+        sent.extend(receive_all(&peer));
+    }
+    assert_eq!(sent, b"from the device");
+
+    let packet = SerialPacket {
+        stream_name: String::from("Serial1"),
+        bit_rate_index: 0,
+        mode: 0,
+        channel: 0,
+        format: SerialFormat::Generic,
+        frame_counter: 0,
+        data: b"from the network".to_vec(),
+    };
+    peer.send_to(&packet.to_bytes(), bind_address).unwrap();
+    while fs::read(&output).unwrap_or_default() != b"from the network" {
+        assert!(
+            Instant::now() < deadline,
+            "the received data was never written"
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(receive_all(&peer).is_empty(), "received data was sent back");
+
+    let _ = fs::remove_dir_all(&dir);
+}
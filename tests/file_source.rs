@@ -0,0 +1,127 @@
+// Files sent through a sender, checked packet by packet on a plain UDP
+// socket standing in for the receiver
+use std::fs;
+use std::net::UdpSocket;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use vban::{DataFormat, FileSource, SenderConfig, Target, VbanSender, decode_packet};
+
+const RATE: u32 = 48000;
+const CHANNELS: usize = 2;
+
+/// Frames in every full packet, as many as VBAN allows
+const PACKET_FRAMES: usize = 256;
+
+/// Writes 16-bit PCM samples as a WAV file
+fn write_wav(path: &Path, samples: &[i16]) {
+    let data_len = (samples.len() * 2) as u32;
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data_len).to_le_bytes());
+    bytes.extend_from_slice(b"WAVEfmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    bytes.extend_from_slice(&(CHANNELS as u16).to_le_bytes());
+    bytes.extend_from_slice(&RATE.to_le_bytes());
+    bytes.extend_from_slice(&(RATE * CHANNELS as u32 * 2).to_le_bytes());
+    bytes.extend_from_slice(&(CHANNELS as u16 * 2).to_le_bytes());
+    bytes.extend_from_slice(&16u16.to_le_bytes());
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        bytes.extend_from_slice(&sample.to_le_bytes());
+    }
+    fs::write(path, bytes).unwrap();
+}
+
+/// A WAV file of `frames` frames of a ramp, so that every sample tells
+/// where it came from
+fn test_file(name: &str, frames: usize) -> (PathBuf, Vec<f32>) {
+    let path = std::env::temp_dir().join(format!("vban-{}-{}.wav", name, std::process::id()));
+    let samples: Vec<i16> = (0..frames * CHANNELS)
+        .map(|i| (i % 30000) as i16 - 15000)
+        .collect();
+    write_wav(&path, &samples);
+    let samples = samples.iter().map(|&x| x as f32 / 32768.0).collect();
+    (path, samples)
+}
+
+/// Starts sending `path` as I16 to a new socket
+fn send(path: &Path, looping: bool) -> (VbanSender, UdpSocket) {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_millis(300)))
+        .unwrap();
+    let source = FileSource::open(path, looping).unwrap();
+    let config = SenderConfig {
+        data_format: DataFormat::I16,
+        ..SenderConfig::new(vec![Target::new(socket.local_addr().unwrap().to_string())])
+    };
+    (VbanSender::start(source, config).unwrap(), socket)
+}
+
+/// The samples of every packet arriving until `until` or a pause, with the
+/// time from the first packet to the last
+fn receive(socket: &UdpSocket, until: Instant) -> (Vec<f32>, Duration) {
+    let mut buffer = [0u8; 2048];
+    let mut samples = Vec::new();
+    let mut first = None;
+    let mut last = Instant::now();
+    while Instant::now() < until
+        && let Ok(amt) = socket.recv(&mut buffer)
+    {
+        let (header, decoded) = decode_packet(&buffer[..amt]).unwrap();
+        assert_eq!(header.channels as usize, CHANNELS);
+        assert!(header.samples_per_frame as usize <= PACKET_FRAMES);
+        last = Instant::now();
+        first.get_or_insert(last);
+        samples.extend(decoded);
+    }
+    (samples, last - first.unwrap_or(last))
+}
+
+#[test]
+fn file_is_sent_in_real_time_and_ends() {
+    let (path, samples) = test_file("real-time", RATE as usize / 2);
+    let (sender, socket) = send(&path, false);
+    let (received, elapsed) = receive(&socket, Instant::now() + Duration::from_secs(10));
+    let _ = fs::remove_file(&path);
+
+    // the end of the file goes out in a last, shorter packet
+    assert_eq!(samples.len() % (PACKET_FRAMES * CHANNELS), 192 * CHANNELS);
+    assert_eq!(received, samples);
+    // paced by the file's rate rather than sent at once
+    assert!(
+        elapsed > Duration::from_millis(400) && elapsed < Duration::from_millis(800),
+        "half a second of audio sent in {:?}",
+        elapsed
+    );
+    // the sender stops by itself at the end of the file
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while !sender.is_finished() {
+        assert!(Instant::now() < deadline, "the sender never finished");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn looping_file_starts_over() {
+    let frames = RATE as usize / 10;
+    let (path, samples) = test_file("looping", frames);
+    let (sender, socket) = send(&path, true);
+    let (received, elapsed) = receive(&socket, Instant::now() + Duration::from_millis(500));
+    assert!(!sender.is_finished());
+    sender.stop();
+    let _ = fs::remove_file(&path);
+
+    assert!(
+        elapsed > Duration::from_millis(350),
+        "sent for {:?}",
+        elapsed
+    );
+    assert!(received.len() >= samples.len() * 3);
+    for pass in received.chunks(samples.len()) {
+        assert_eq!(pass, &samples[..pass.len()]);
+    }
+}
@@ -0,0 +1,317 @@
+// End-to-end tests sending known signals from a memory source through a
+// sender and a receiver on 127.0.0.1 into a memory sink
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use cpal::SampleRate;
+use vban::header::VBAN_SAMPLE_RATES;
+use vban::{
+    DataFormat, JitterConfig, MemorySink, MemorySource, ReceiverConfig, RecordConfig, SenderConfig,
+    Target, VbanReceiver, VbanSender,
+};
+
+const FORMATS: [DataFormat; 8] = [
+    DataFormat::U8,
+    DataFormat::I16,
+    DataFormat::I24,
+    DataFormat::I32,
+    DataFormat::F32,
+    DataFormat::F64,
+    DataFormat::Bits12,
+    DataFormat::Bits10,
+];
+
+/// Real-time runs are timing sensitive, so only one runs at a time however
+/// many tests run in parallel
+static LOOPBACK: Mutex<()> = Mutex::new(());
+
+/// Audio the receiver buffers before playing
+const LATENCY: Duration = Duration::from_millis(60);
+
+/// How far beyond `LATENCY` playback may start
+const LATENCY_SLACK: Duration = Duration::from_millis(60);
+
+/// Rounds `x` to a value `format` carries exactly
+fn quantize(x: f32, format: DataFormat) -> f32 {
+    match format {
+        DataFormat::F32 | DataFormat::F64 => x,
+        _ => {
+            let scale = (1u64 << (format.bits_per_sample() - 1)) as f32;
+            (x * scale).round() / scale
+        }
+    }
+}
+
+/// A sine sweep up to a quarter of the rate followed by an impulse train,
+/// scaled and inverted differently on every channel so that mixed up
+/// channels are noticed
+fn test_signal(rate: u32, channels: usize, duration: Duration, format: DataFormat) -> Vec<f32> {
+    let frames = (duration.as_secs_f64() * rate as f64) as usize;
+    let sweep_frames = frames / 2;
+    let (start, end) = (50.0, rate as f64 / 4.0);
+    let mut phase = 0.0f64;
+    let mut samples = Vec::with_capacity(frames * channels);
+    for frame in 0..frames {
+        let x = if frame < sweep_frames {
+            let frequency = start + (end - start) * frame as f64 / sweep_frames as f64;
+            phase += std::f64::consts::TAU * frequency / rate as f64;
+            // a cosine, so that the signal does not start with silence
+            (0.7 * phase.cos()) as f32
+        } else if (frame - sweep_frames).is_multiple_of(50) {
+            0.9
+        } else {
+            0.0
+        };
+        for channel in 0..channels {
+            let gain = 1.0 - 0.5 * channel as f32 / channels as f32;
+            let sign = if channel % 2 == 0 { 1.0 } else { -1.0 };
+            samples.push(quantize(x * gain * sign, format));
+        }
+    }
+    samples
+}
+
+/// What came out of the sink, and how the receiver fared
+struct Received {
+    samples: Vec<f32>,
+    underruns: u64,
+    lost: u64,
+}
+
+/// A receiver on an ephemeral port buffering `LATENCY`
+fn receiver_config() -> ReceiverConfig {
+    ReceiverConfig {
+        jitter: JitterConfig {
+            target: LATENCY,
+            min: Duration::ZERO,
+            max: Duration::from_secs(1),
+        },
+        ..ReceiverConfig::new("127.0.0.1:0".parse().unwrap())
+    }
+}
+
+/// Plays `samples` through a sender and a receiver, followed by enough
+/// silence to flush the last packet, and returns everything the sink played
+fn loopback(
+    samples: &[f32],
+    source_rate: u32,
+    stream_rate: u32,
+    channels: usize,
+    format: DataFormat,
+) -> Received {
+    loopback_with(
+        receiver_config(),
+        samples,
+        source_rate,
+        stream_rate,
+        channels,
+        format,
+    )
+}
+
+/// `loopback` through a receiver set up with `config`
+fn loopback_with(
+    config: ReceiverConfig,
+    samples: &[f32],
+    source_rate: u32,
+    stream_rate: u32,
+    channels: usize,
+    format: DataFormat,
+) -> Received {
+    let _running = LOOPBACK.lock().unwrap_or_else(|x| x.into_inner());
+    let sink = MemorySink::new(channels);
+    let receiver = VbanReceiver::start(Some(Box::new(sink.clone())), config).unwrap();
+    let stats = receiver.stats();
+    let target: SocketAddr = receiver.local_addr();
+
+    let mut input = samples.to_vec();
+    input.resize(samples.len() + (source_rate as usize / 10) * channels, 0.0);
+    let source = MemorySource::new(input, SampleRate(source_rate), channels);
+    let config = SenderConfig {
+        stream_rate: Some(SampleRate(stream_rate)),
+        data_format: format,
+        ..SenderConfig::new(vec![Target::new(target.to_string())])
+    };
+    let sender = VbanSender::start(source, config).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while !sender.is_finished() {
+        assert!(Instant::now() < deadline, "the sender never finished");
+        thread::sleep(Duration::from_millis(10));
+    }
+    thread::sleep(LATENCY + LATENCY_SLACK);
+    receiver.stop();
+
+    Received {
+        samples: sink.take(),
+        underruns: stats.underruns.load(Ordering::Relaxed),
+        lost: stats.lost.load(Ordering::Relaxed),
+    }
+}
+
+/// The frame at which `expected` starts in `output`
+fn find(output: &[f32], expected: &[f32], channels: usize) -> Option<usize> {
+    (0..=output.len().checked_sub(expected.len())? / channels)
+        .find(|&frame| output[frame * channels..][..expected.len()] == *expected)
+}
+
+/// Sends the test signal and checks it comes back unchanged and in time
+fn assert_bit_exact(format: DataFormat, rate: u32, channels: usize) {
+    let signal = test_signal(rate, channels, Duration::from_millis(250), format);
+    let received = loopback(&signal, rate, rate, channels, format);
+    let context = format!("{:?} at {} Hz with {} channels", format, rate, channels);
+    assert_eq!(received.lost, 0, "packets lost for {}", context);
+    // the end of the stream runs the buffer dry once
+    assert!(received.underruns <= 1, "underruns for {}", context);
+    let start = find(&received.samples, &signal, channels)
+        .unwrap_or_else(|| panic!("signal not played back unchanged for {}", context));
+    let latency = Duration::from_secs_f64(start as f64 / rate as f64);
+    assert!(
+        latency <= LATENCY + LATENCY_SLACK,
+        "playback started after {:?} for {}",
+        latency,
+        context
+    );
+}
+
+#[test]
+fn every_format_and_channel_count_is_bit_exact() {
+    for format in FORMATS {
+        for channels in [1, 2, 8] {
+            assert_bit_exact(format, 48000, channels);
+        }
+    }
+}
+
+#[test]
+fn wide_streams_are_bit_exact() {
+    // a low rate keeps the packet rate of the widest formats manageable
+    for format in FORMATS {
+        assert_bit_exact(format, 8000, 64);
+    }
+}
+
+#[test]
+fn every_sample_rate_is_bit_exact() {
+    for rate in VBAN_SAMPLE_RATES {
+        assert_bit_exact(DataFormat::I16, rate, 2);
+    }
+}
+
+/// Signal to noise ratio in dB of a sine of `frequency` in `samples` of a
+/// single channel, fitting its amplitude and phase
+fn sine_snr(samples: &[f32], frequency: f64, rate: u32) -> f64 {
+    let step = std::f64::consts::TAU * frequency / rate as f64;
+    let (mut ss, mut sc, mut cc, mut ys, mut yc) = (0.0, 0.0, 0.0, 0.0, 0.0);
+    for (i, &y) in samples.iter().enumerate() {
+        let (s, c) = (i as f64 * step).sin_cos();
+        ss += s * s;
+        sc += s * c;
+        cc += c * c;
+        ys += y as f64 * s;
+        yc += y as f64 * c;
+    }
+    // least squares solution of y = a sin + b cos
+    let det = ss * cc - sc * sc;
+    let a = (ys * cc - yc * sc) / det;
+    let b = (yc * ss - ys * sc) / det;
+    let (mut signal, mut noise) = (0.0, 0.0);
+    for (i, &y) in samples.iter().enumerate() {
+        let (s, c) = (i as f64 * step).sin_cos();
+        let fit = a * s + b * c;
+        signal += fit * fit;
+        noise += (y as f64 - fit).powi(2);
+    }
+    10.0 * (signal / noise).log10()
+}
+
+#[test]
+fn resampled_stream_keeps_snr() {
+    let (source_rate, stream_rate, frequency) = (44100, 48000, 1000.0);
+    let signal: Vec<f32> = (0..source_rate / 2)
+        .map(|i| {
+            let t = i as f64 / source_rate as f64;
+            (0.5 * (std::f64::consts::TAU * frequency * t).sin()) as f32
+        })
+        .collect();
+    let received = loopback(&signal, source_rate, stream_rate, 1, DataFormat::F32);
+
+    // skip the silence while buffering and the resampler settling
+    let start = received
+        .samples
+        .iter()
+        .position(|x| x.abs() > 0.01)
+        .expect("nothing played");
+    let latency = Duration::from_secs_f64(start as f64 / stream_rate as f64);
+    assert!(
+        latency <= LATENCY + LATENCY_SLACK,
+        "playback started after {:?}",
+        latency
+    );
+    let steady = &received.samples[start + stream_rate as usize / 20..][..stream_rate as usize / 5];
+    let snr = sine_snr(steady, frequency, stream_rate);
+    assert!(snr > 60.0, "SNR of {:.1} dB", snr);
+}
+
+#[test]
+fn stream_plays_when_it_cannot_be_recorded() {
+    let dir = std::env::temp_dir().join(format!("vban-unrecordable-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    // FLAC carries at most 8 channels
+    let config = ReceiverConfig {
+        record: Some(RecordConfig::new(dir.join("wide.flac"))),
+        ..receiver_config()
+    };
+    let (rate, channels) = (8000, 16);
+    let signal = test_signal(rate, channels, Duration::from_millis(250), DataFormat::F32);
+    let received = loopback_with(config, &signal, rate, rate, channels, DataFormat::F32);
+    assert!(find(&received.samples, &signal, channels).is_some());
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn streams_are_mixed_with_their_gains() {
+    let _running = LOOPBACK.lock().unwrap_or_else(|x| x.into_inner());
+    let (rate, channels) = (48000, 2);
+    let sink = MemorySink::new(channels);
+    let config = ReceiverConfig {
+        gains: [(String::from("Quiet"), 0.5)].into_iter().collect(),
+        ..receiver_config()
+    };
+    let receiver = VbanReceiver::start(Some(Box::new(sink.clone())), config).unwrap();
+    let target = receiver.local_addr().to_string();
+
+    // constant signals, so that the mix is the same wherever both play
+    let send = |name: &str, level: f32| {
+        let samples = vec![level; rate as usize / 2 * channels];
+        let source = MemorySource::new(samples, SampleRate(rate), channels);
+        let config = SenderConfig {
+            stream_name: String::from(name),
+            ..SenderConfig::new(vec![Target::new(target.clone())])
+        };
+        VbanSender::start(source, config).unwrap()
+    };
+    let senders = [send("Loud", 0.125), send("Quiet", 0.5)];
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while !senders.iter().all(VbanSender::is_finished) {
+        assert!(Instant::now() < deadline, "the senders never finished");
+        thread::sleep(Duration::from_millis(10));
+    }
+    thread::sleep(LATENCY + LATENCY_SLACK);
+    receiver.stop();
+
+    // both streams come from 127.0.0.1 and are told apart by name
+    let samples = sink.take();
+    let mixed = samples.iter().filter(|&&x| x == 0.125 + 0.5 * 0.5).count();
+    // most of the half second both play
+    assert!(
+        mixed >= rate as usize * channels * 4 / 10,
+        "only {} of {} samples hold the mix",
+        mixed,
+        samples.len()
+    );
+    assert!(samples.iter().all(|&x| x <= 0.375));
+}
@@ -0,0 +1,328 @@
+// Round trips of every sub-protocol through its encoder and decoder, and
+// decoders fed arbitrary datagrams, which must fail cleanly rather than panic
+use std::sync::Arc;
+use std::time::Duration;
+
+use proptest::prelude::*;
+use vban::codec::decode_samples;
+use vban::header::{self, VBAN_HEADER_SIZE, VBAN_MAGIC};
+use vban::sequence::Sequencer;
+use vban::{
+    Codec, DataFormat, Encoder, Identity, PingPacket, ReceiverStats, SerialFormat, SerialPacket,
+    SubProtocol, TextFormat, TextPacket, VbanHeader, decode_packet,
+};
+
+const FORMATS: [DataFormat; 8] = [
+    DataFormat::U8,
+    DataFormat::I16,
+    DataFormat::I24,
+    DataFormat::I32,
+    DataFormat::F32,
+    DataFormat::F64,
+    DataFormat::Bits12,
+    DataFormat::Bits10,
+];
+
+fn data_format() -> impl Strategy<Value = DataFormat> {
+    proptest::sample::select(FORMATS.to_vec())
+}
+
+fn sub_protocol() -> impl Strategy<Value = SubProtocol> {
+    proptest::sample::select(vec![
+        SubProtocol::Audio,
+        SubProtocol::Serial,
+        SubProtocol::Text,
+        SubProtocol::Service,
+        SubProtocol::Undefined1,
+        SubProtocol::Undefined2,
+        SubProtocol::Undefined3,
+        SubProtocol::User,
+    ])
+}
+
+fn codec() -> impl Strategy<Value = Codec> {
+    (0u8..16).prop_map(|x| match x << 4 {
+        0x00 => Codec::Pcm,
+        0x10 => Codec::Vbca,
+        0x20 => Codec::Vbcv,
+        0xF0 => Codec::User,
+        x => Codec::Undefined(x),
+    })
+}
+
+/// Stream names as they come out of a header, without nulls
+fn stream_name() -> impl Strategy<Value = String> {
+    "[A-Za-z0-9 _-]{0,16}"
+}
+
+fn vban_header() -> impl Strategy<Value = VbanHeader> {
+    (
+        sub_protocol(),
+        0u8..32,
+        1u16..=256,
+        1u16..=256,
+        data_format(),
+        codec(),
+        any::<[u8; 16]>(),
+        any::<u32>(),
+    )
+        .prop_map(
+            |(
+                sub_protocol,
+                sample_rate_index,
+                samples_per_frame,
+                channels,
+                data_format,
+                codec,
+                stream_name,
+                frame_counter,
+            )| VbanHeader {
+                sub_protocol,
+                sample_rate_index,
+                samples_per_frame,
+                channels,
+                data_format,
+                codec,
+                stream_name,
+                frame_counter,
+            },
+        )
+}
+
+/// A sample `format` carries exactly
+fn exact_sample(format: DataFormat) -> BoxedStrategy<f32> {
+    match format {
+        DataFormat::F32 | DataFormat::F64 => (-1.0f32..1.0).boxed(),
+        // keep away from full scale, which f32 cannot hold at 32 bits
+        DataFormat::I32 => (-(1i64 << 30)..(1i64 << 30))
+            .prop_map(|x| (x as f64 / (1u64 << 31) as f64) as f32)
+            .boxed(),
+        _ => {
+            let scale = 1i64 << (format.bits_per_sample() - 1);
+            (-scale..scale)
+                .prop_map(move |x| (x as f64 / scale as f64) as f32)
+                .boxed()
+        }
+    }
+}
+
+/// A format, a channel count and interleaved samples of a whole number of
+/// frames, few enough to fit in one packet
+fn audio() -> impl Strategy<Value = (DataFormat, u16, Vec<f32>)> {
+    (data_format(), 1u16..=8, 1usize..=16).prop_flat_map(|(format, channels, frames)| {
+        (
+            Just(format),
+            Just(channels),
+            proptest::collection::vec(exact_sample(format), frames * channels as usize),
+        )
+    })
+}
+
+fn identity() -> impl Strategy<Value = Identity> {
+    (
+        any::<[u32; 7]>(),
+        any::<[u8; 4]>(),
+        proptest::collection::vec("[A-Za-z0-9 .-]{0,8}", 10),
+        any::<u16>(),
+    )
+        .prop_map(|(numbers, version, strings, distant_port)| Identity {
+            device_type: numbers[0],
+            features: numbers[1],
+            features_ex: numbers[2],
+            preferred_rate: numbers[3],
+            min_rate: numbers[4],
+            max_rate: numbers[5],
+            color: numbers[6],
+            version,
+            gps_position: strings[0].clone(),
+            user_position: strings[1].clone(),
+            language: strings[2].clone(),
+            distant_ip: strings[3].clone(),
+            distant_port,
+            device_name: strings[4].clone(),
+            manufacturer_name: strings[5].clone(),
+            application_name: strings[6].clone(),
+            host_name: strings[7].clone(),
+            user_name: strings[8].clone(),
+            user_comment: strings[9].clone(),
+        })
+}
+
+/// Arbitrary datagrams, half of them starting with the VBAN magic so that
+/// the decoders get past the first check
+fn datagram() -> impl Strategy<Value = Vec<u8>> {
+    (
+        any::<bool>(),
+        proptest::collection::vec(any::<u8>(), 0..1600),
+    )
+        .prop_map(|(magic, bytes)| {
+            if magic {
+                [&VBAN_MAGIC[..], &bytes].concat()
+            } else {
+                bytes
+            }
+        })
+}
+
+proptest! {
+    #[test]
+    fn header_round_trips(header in vban_header()) {
+        let bytes = header.to_bytes();
+        prop_assert_eq!(VbanHeader::from_bytes(&bytes).unwrap(), header);
+    }
+
+    #[test]
+    fn audio_round_trips(
+        (format, channels, samples) in audio(),
+        sample_rate_index in 0u8..header::VBAN_SAMPLE_RATES.len() as u8,
+        stream_name in stream_name(),
+        frame_counter in any::<u32>(),
+    ) {
+        let mut header = VbanHeader {
+            sample_rate_index,
+            channels,
+            data_format: format,
+            frame_counter,
+            ..Default::default()
+        };
+        header.set_stream_name(&stream_name);
+        let frames = samples.len() / channels as usize;
+        let mut encoder = Encoder::new(header.clone(), Some(frames)).unwrap();
+        let packets = encoder.encode(&samples);
+        prop_assert_eq!(packets.len(), 1);
+
+        let (decoded_header, decoded) = decode_packet(&packets[0]).unwrap();
+        prop_assert_eq!(decoded_header.samples_per_frame as usize, frames);
+        prop_assert_eq!(decoded_header.stream_name(), stream_name);
+        prop_assert_eq!(decoded_header.frame_counter, frame_counter);
+        prop_assert_eq!(decoded, samples);
+    }
+
+    #[test]
+    fn audio_split_into_packets_round_trips(
+        (format, channels, samples) in audio(),
+        packet_frames in 1usize..=16,
+    ) {
+        let header = VbanHeader { channels, data_format: format, ..Default::default() };
+        let mut encoder = Encoder::new(header, Some(packet_frames)).unwrap();
+        let mut packets = encoder.encode(&samples);
+        packets.extend(encoder.flush());
+        prop_assert!(encoder.flush().is_none());
+
+        let mut decoded = Vec::new();
+        for (i, packet) in packets.iter().enumerate() {
+            let (header, samples) = decode_packet(packet).unwrap();
+            prop_assert_eq!(header.frame_counter, i as u32);
+            prop_assert!(header.samples_per_frame as usize <= packet_frames);
+            decoded.extend(samples);
+        }
+        prop_assert_eq!(decoded, samples);
+    }
+
+    #[test]
+    fn text_round_trips(
+        stream_name in stream_name(),
+        bit_rate_index in 0u8..header::VBAN_BIT_RATES.len() as u8,
+        channel in any::<u8>(),
+        format in proptest::sample::select(vec![TextFormat::Ascii, TextFormat::Utf8, TextFormat::Wchar]),
+        frame_counter in any::<u32>(),
+        text in "[ -~]{0,300}",
+    ) {
+        let packet = TextPacket { stream_name, bit_rate_index, channel, format, frame_counter, text };
+        prop_assert_eq!(TextPacket::from_bytes(&packet.to_bytes()).unwrap(), packet);
+    }
+
+    #[test]
+    fn serial_round_trips(
+        stream_name in stream_name(),
+        bit_rate_index in 0u8..header::VBAN_BIT_RATES.len() as u8,
+        mode in any::<u8>(),
+        channel in any::<u8>(),
+        format in proptest::sample::select(vec![SerialFormat::Generic, SerialFormat::Midi]),
+        frame_counter in any::<u32>(),
+        data in proptest::collection::vec(any::<u8>(), 0..=header::VBAN_MAX_PAYLOAD_SIZE),
+    ) {
+        let packet = SerialPacket { stream_name, bit_rate_index, mode, channel, format, frame_counter, data };
+        prop_assert_eq!(SerialPacket::from_bytes(&packet.to_bytes()).unwrap(), packet);
+    }
+
+    #[test]
+    fn ping_round_trips(reply in any::<bool>(), frame_counter in any::<u32>(), identity in identity()) {
+        let packet = PingPacket { reply, frame_counter, identity };
+        prop_assert_eq!(PingPacket::from_bytes(&packet.to_bytes()).unwrap(), packet);
+    }
+
+    #[test]
+    fn decoders_survive_arbitrary_datagrams(bytes in datagram()) {
+        let _ = VbanHeader::from_bytes(&bytes);
+        let _ = header::parse_audio_packet(&bytes);
+        let _ = decode_packet(&bytes);
+        let _ = TextPacket::from_bytes(&bytes);
+        let _ = SerialPacket::from_bytes(&bytes);
+        let _ = PingPacket::from_bytes(&bytes);
+    }
+
+    #[test]
+    fn decode_samples_checks_the_payload_length(
+        format in data_format(),
+        payload in proptest::collection::vec(any::<u8>(), 0..2048),
+        count in 0usize..4096,
+    ) {
+        let decoded = decode_samples(format, &payload, count);
+        if format.payload_size(count) <= payload.len() {
+            prop_assert_eq!(decoded.unwrap().len(), count);
+        } else {
+            prop_assert!(decoded.is_err());
+        }
+    }
+
+    #[test]
+    fn decode_samples_survives_huge_counts(format in data_format(), count in any::<usize>()) {
+        prop_assert!(decode_samples(format, &[0; 64], count.max(65)).is_err());
+    }
+
+    #[test]
+    fn corrupted_audio_packets_fail_cleanly(
+        (format, channels, samples) in audio(),
+        corruption in proptest::collection::vec((0usize..VBAN_HEADER_SIZE + 64, any::<u8>()), 1..8),
+        truncate in proptest::option::of(0usize..VBAN_HEADER_SIZE + 64),
+    ) {
+        let header = VbanHeader { channels, data_format: format, ..Default::default() };
+        let frames = samples.len() / channels as usize;
+        let mut packet = Encoder::new(header, Some(frames)).unwrap().encode(&samples).remove(0);
+        for (i, byte) in corruption {
+            if let Some(x) = packet.get_mut(i) {
+                *x = byte;
+            }
+        }
+        if let Some(len) = truncate {
+            packet.truncate(len);
+        }
+        if let Ok((header, decoded)) = decode_packet(&packet) {
+            prop_assert_eq!(
+                decoded.len(),
+                header.samples_per_frame as usize * header.channels as usize
+            );
+        }
+    }
+
+    #[test]
+    fn sequencer_survives_arbitrary_frame_counters(
+        start in any::<u32>(),
+        packets in proptest::collection::vec((-3000i32..3000, 1u16..=256), 1..200),
+    ) {
+        let mut sequencer =
+            Sequencer::new(Duration::from_millis(20), 2, Arc::new(ReceiverStats::default()));
+        for (offset, samples_per_frame) in packets {
+            let header = VbanHeader {
+                channels: 2,
+                samples_per_frame,
+                frame_counter: start.wrapping_add_signed(offset),
+                ..Default::default()
+            };
+            for samples in sequencer.push(&header, vec![0.5; samples_per_frame as usize * 2]) {
+                prop_assert!(samples.len() <= 256 * 2);
+            }
+        }
+    }
+}